@@ -28,16 +28,17 @@ fn main() {
 
     task::spawn_with_config(
         || {
-            info!("task2");
-
-            // loop {
-            //     spin_loop();
-            // }
+            // 忙等也会被时间片抢占，不会饿死主任务
+            loop {
+                spin_delay(Duration::from_secs(1));
+                info!("task2");
+            }
         },
         TaskConfig {
             name: "task2".to_string(),
             priority: 0,
             stack_size: 0x1000 * 4,
+            address_space: None,
         },
     )
     .unwrap();
@@ -2,6 +2,8 @@
 #![no_main]
 #![feature(used_with_arg)]
 
+extern crate alloc;
+
 #[bare_test::tests]
 mod tests {
 
@@ -26,4 +28,791 @@ mod tests {
 
         println!("test2: data phys: {phys:#x}");
     }
+
+    #[test]
+    fn test_task_join() {
+        let handle = task::spawn_with_config(
+            || {
+                time::sleep(core::time::Duration::from_millis(10));
+                42
+            },
+            task::TaskConfig::new("join"),
+        )
+        .unwrap();
+
+        assert_eq!(handle.join(), 42);
+    }
+
+    #[test]
+    fn test_sync_event() {
+        use alloc::sync::Arc;
+        use core::time::Duration;
+
+        let event = Arc::new(sync::Event::new());
+        let counter = Arc::new(sync::Mutex::new(0));
+
+        let handle = task::spawn_with_config(
+            {
+                let event = event.clone();
+                let counter = counter.clone();
+                move || {
+                    *counter.lock() += 1;
+                    event.set();
+                }
+            },
+            task::TaskConfig::new("event"),
+        )
+        .unwrap();
+
+        assert!(event.wait_timeout(Duration::from_secs(1)));
+        handle.join();
+        assert_eq!(*counter.lock(), 1);
+        assert!(!sync::Event::new().wait_timeout(Duration::from_millis(10)));
+    }
+
+    #[test]
+    fn test_async_spawn() {
+        use core::time::Duration;
+
+        let handle = async_std::spawn(async {
+            async_std::time::sleep(Duration::from_millis(10)).await;
+            7
+        });
+
+        assert_eq!(async_std::block_on(handle), 7);
+    }
+
+    #[test]
+    fn test_address_space() {
+        use alloc::sync::Arc;
+        use core::alloc::Layout;
+        use mem::{
+            Phys, Virt,
+            mmu::{AccessSetting, AddressSpace, CacheSetting},
+        };
+
+        const USER_VA: usize = 0x4000_0000;
+
+        let layout = Layout::from_size_align(0x1000, 0x1000).unwrap();
+        let page = unsafe { alloc::alloc::alloc_zeroed(layout) };
+        let pa: Phys<u8> = Virt::<u8>::from(page as usize).into();
+
+        let aspace = AddressSpace::new().unwrap();
+        aspace
+            .map(&hal_al::mmu::MapConfig {
+                name: "test",
+                va_start: USER_VA.into(),
+                pa_start: pa,
+                size: 0x1000,
+                access: AccessSetting::ReadWrite,
+                cache: CacheSetting::Normal,
+            })
+            .unwrap();
+
+        let mut config = task::TaskConfig::new("aspace");
+        config.address_space = Some(Arc::new(aspace));
+        task::spawn_with_config(
+            || unsafe { (USER_VA as *mut u32).write_volatile(0x5a5a) },
+            config,
+        )
+        .unwrap()
+        .join();
+
+        assert_eq!(unsafe { (page as *const u32).read_volatile() }, 0x5a5a);
+        unsafe { alloc::alloc::dealloc(page, layout) };
+    }
+
+    /// 只有一个可读可执行段的 ELF，`code` 之后紧跟 `data`
+    fn user_elf(va: u64, code: &[u32], data: &[u8]) -> alloc::vec::Vec<u8> {
+        const CODE_OFFSET: u64 = 0x78;
+        let size = (code.len() * 4 + data.len()) as u64;
+
+        let mut elf = alloc::vec::Vec::new();
+        elf.extend_from_slice(b"\x7fELF\x02\x01\x01\0\0\0\0\0\0\0\0\0");
+        elf.extend_from_slice(&2u16.to_le_bytes()); // ET_EXEC
+        elf.extend_from_slice(&183u16.to_le_bytes()); // EM_AARCH64
+        elf.extend_from_slice(&1u32.to_le_bytes());
+        elf.extend_from_slice(&va.to_le_bytes()); // e_entry
+        elf.extend_from_slice(&0x40u64.to_le_bytes()); // e_phoff
+        elf.extend_from_slice(&0u64.to_le_bytes()); // e_shoff
+        elf.extend_from_slice(&0u32.to_le_bytes());
+        for v in [0x40u16, 56, 1, 64, 0, 0] {
+            elf.extend_from_slice(&v.to_le_bytes());
+        }
+        elf.extend_from_slice(&1u32.to_le_bytes()); // PT_LOAD
+        elf.extend_from_slice(&5u32.to_le_bytes()); // R-X
+        for v in [CODE_OFFSET, va, va, size, size, 0x1000] {
+            elf.extend_from_slice(&v.to_le_bytes());
+        }
+        assert_eq!(elf.len() as u64, CODE_OFFSET);
+        for c in code {
+            elf.extend_from_slice(&c.to_le_bytes());
+        }
+        elf.extend_from_slice(data);
+        elf
+    }
+
+    #[test]
+    fn test_user_task() {
+        let code: [u32; 8] = [
+            0x1000_0101, // adr x1, msg
+            0xd280_0020, // mov x0, #1
+            0xd280_0182, // mov x2, #12
+            0xd280_0808, // mov x8, #64 (write)
+            0xd400_0001, // svc #0
+            0xd280_0540, // mov x0, #42
+            0xd280_0ba8, // mov x8, #93 (exit)
+            0xd400_0001, // svc #0
+        ];
+        let elf = user_elf(0x40_0000, &code, b"hello, EL0!\n");
+
+        let handle = task::spawn_user(&elf, task::TaskConfig::new("user")).unwrap();
+        assert_eq!(handle.join(), 42);
+    }
+
+    #[test]
+    fn test_user_kernel_access_faults() {
+        static SECRET: u64 = 0x5ec2e7;
+
+        // movz/movk x1 拼出内核地址，再从 EL0 读取
+        let addr = &SECRET as *const u64 as u64;
+        let mut code = alloc::vec::Vec::new();
+        for hw in 0..4u32 {
+            let imm = ((addr >> (16 * hw)) & 0xffff) as u32;
+            let op = if hw == 0 { 0xd280_0000 } else { 0xf280_0000 };
+            code.push(op | (hw << 21) | (imm << 5) | 1);
+        }
+        code.extend_from_slice(&[
+            0xf940_0020, // ldr x0, [x1]
+            0xd280_0540, // mov x0, #42
+            0xd280_0ba8, // mov x8, #93 (exit)
+            0xd400_0001, // svc #0
+        ]);
+        let elf = user_elf(0x40_0000, &code, &[]);
+
+        let handle = task::spawn_user(&elf, task::TaskConfig::new("peek")).unwrap();
+        assert_eq!(handle.join(), -1, "EL0 read kernel memory");
+
+        let (_, flags) = mem::translate(mem::VirtAddr::from(addr as usize)).unwrap();
+        assert!(!flags.user);
+    }
+
+    #[test]
+    fn test_fault_kills_task() {
+        let handle = task::spawn_with_config(
+            || unsafe { (0x10 as *const u32).read_volatile() },
+            task::TaskConfig::new("fault"),
+        )
+        .unwrap();
+        assert_eq!(handle.try_join(), None);
+    }
+
+    #[test]
+    fn test_stack_overflow() {
+        #[allow(unconditional_recursion)]
+        fn recurse(depth: usize) -> usize {
+            let buf = core::hint::black_box([depth as u8; 256]);
+            recurse(depth + 1) + buf[0] as usize
+        }
+
+        let mut config = task::TaskConfig::new("overflow");
+        config.stack_size = 0x4000;
+        let handle = task::spawn_with_config(|| recurse(0), config).unwrap();
+        assert_eq!(handle.try_join(), None);
+    }
+
+    #[test]
+    fn test_frame_zones() {
+        use core::alloc::Layout;
+        use mem::frame::{DMA32_END, FRAMES, Zone};
+
+        let before = FRAMES.stats(Zone::Dma32);
+        let layout = Layout::from_size_align(0x3000, 0x1000).unwrap();
+        let addr = FRAMES.alloc(layout, Zone::Dma32).unwrap();
+        assert!(addr.raw() + layout.size() <= DMA32_END);
+        assert_eq!(FRAMES.stats(Zone::Dma32).free, before.free - 3);
+
+        FRAMES.dealloc(addr, layout);
+        assert_eq!(FRAMES.stats(Zone::Dma32).free, before.free);
+    }
+
+    #[test]
+    fn test_protect_translate() {
+        use mem::mmu::AccessSetting;
+
+        let stack = mem::stack::KernelStack::new(0x2000).unwrap();
+        let va = mem::VirtAddr::from(stack.bottom());
+        let (_, flags) = mem::translate(va).unwrap();
+        assert_eq!(flags.access, AccessSetting::ReadWrite);
+        assert!(!flags.user);
+
+        mem::protect(va, 0x1000, AccessSetting::Read).unwrap();
+        assert_eq!(mem::translate(va).unwrap().1.access, AccessSetting::Read);
+        assert!(
+            mem::translate(va - 0x1000).is_none(),
+            "guard page is mapped"
+        );
+    }
+
+    #[test]
+    fn test_vmalloc() {
+        use mem::vmalloc::{vfree, vmalloc};
+
+        let size = 4 * 1024 * 1024;
+        let ptr = vmalloc(size).unwrap();
+        let buf = unsafe { core::slice::from_raw_parts_mut(ptr.as_ptr(), size) };
+        buf.fill(0x5a);
+        assert_eq!(buf[size - 1], 0x5a);
+        unsafe { vfree(ptr) };
+        assert!(mem::translate(mem::VirtAddr::from(ptr)).is_none());
+    }
+
+    #[test]
+    fn test_ioremap_refcount() {
+        use core::alloc::Layout;
+        use mem::{
+            frame::{FRAMES, Zone},
+            ioremap::{IoRemapError, ioremap, iounmap},
+            mmu::CacheSetting,
+        };
+
+        let layout = Layout::from_size_align(0x1000, 0x1000).unwrap();
+        let pa = FRAMES.alloc(layout, Zone::High).unwrap();
+
+        let a = ioremap(pa, 0x1000, CacheSetting::NonCacheable).unwrap();
+        let b = ioremap(pa + 0x100, 4, CacheSetting::NonCacheable).unwrap();
+        assert_eq!(
+            b.as_ptr().as_ptr() as usize,
+            a.as_ptr().as_ptr() as usize + 0x100
+        );
+        assert!(matches!(
+            ioremap(pa, 4, CacheSetting::Device),
+            Err(IoRemapError::Conflict { .. })
+        ));
+
+        a.write::<u32>(0x100, 0xdead_beef);
+        assert_eq!(b.read::<u32>(0), 0xdead_beef);
+
+        let va = mem::VirtAddr::from(a.as_ptr());
+        iounmap(a);
+        assert!(mem::translate(va).is_some());
+        iounmap(b);
+        assert!(mem::translate(va).is_none());
+
+        FRAMES.dealloc(pa, layout);
+    }
+
+    #[test]
+    fn test_mem_stats() {
+        let before = mem::stats();
+        let data = alloc::vec![0u8; 0x1000];
+        let after = mem::stats();
+        drop(data);
+
+        assert!(after.heap.usage.used <= after.heap.usage.total);
+        assert!(after.heap.usage.largest_free <= after.heap.usage.free);
+        assert!(after.heap.allocs > before.heap.allocs);
+        assert!(!after.regions.is_empty());
+        println!("{after}");
+    }
+
+    #[test]
+    fn test_alloc_track() {
+        #[cfg(not(feature = "alloc-track"))]
+        println!("alloc-track disabled, skipped");
+
+        #[cfg(feature = "alloc-track")]
+        {
+            use mem::track;
+
+            let before = track::snapshot();
+            let kept = alloc::vec![0u8; 0x200];
+            let diff = before.diff(&track::snapshot());
+            let pid = task::current().pid;
+            assert!(
+                diff.allocated_by(pid)
+                    .any(|r| r.addr == kept.as_ptr() as usize && r.size == 0x200)
+            );
+
+            assert_no_leak(|| {
+                let tmp = alloc::vec![1u8; 0x100];
+                assert_eq!(tmp.len(), 0x100);
+            });
+            drop(kept);
+        }
+    }
+
+    #[test]
+    fn test_oom_reclaim() {
+        use core::sync::atomic::{AtomicUsize, Ordering};
+        use mem::oom::{self, OomPolicy};
+
+        static CALLS: AtomicUsize = AtomicUsize::new(0);
+        fn reclaim(_needed: usize) -> usize {
+            CALLS.fetch_add(1, Ordering::Relaxed);
+            0
+        }
+
+        oom::register_reclaim("test", reclaim).unwrap();
+        let old = oom::policy();
+        oom::set_policy(OomPolicy::Retry { attempts: 2 });
+
+        let mut v = alloc::vec::Vec::<u8>::new();
+        assert!(v.try_reserve(1 << 40).is_err());
+        assert_eq!(CALLS.load(Ordering::Relaxed), 3);
+
+        // 可失败的分配不受 panic/结束任务策略影响
+        for policy in [OomPolicy::Panic, OomPolicy::KillTask] {
+            oom::set_policy(policy);
+            assert!(v.try_reserve(1 << 40).is_err());
+        }
+
+        oom::set_policy(old);
+        oom::unregister_reclaim("test");
+    }
+
+    #[test]
+    fn test_slab_small_objects() {
+        use alloc::{boxed::Box, vec::Vec};
+
+        let objs: Vec<Box<[u8; 48]>> = (0..100).map(|i| Box::new([i as u8; 48])).collect();
+        for (i, obj) in objs.iter().enumerate() {
+            assert_eq!(obj[47], i as u8);
+            // 48 字节的对象属于 64 字节的类别
+            assert!((obj.as_ptr() as usize).is_multiple_of(64));
+        }
+        drop(objs);
+
+        assert!(mem::stats().heap.slab_cached > 0);
+    }
+
+    #[test]
+    fn test_dma_bounce_and_coherent() {
+        use core::ptr::NonNull;
+        use mem::{
+            dma::{self, Direction},
+            mmu::CacheSetting,
+        };
+
+        let mut buf = alloc::vec![0x5au8; 256];
+        let ptr = NonNull::new(buf.as_mut_ptr()).unwrap();
+        let (pa, _) = mem::translate(mem::VirtAddr::from(ptr)).unwrap();
+        // 设备够不到缓冲区末尾，只能使用回弹缓冲区
+        let mask = (pa.raw() + 0xff - 1) as u64;
+        let mapping = dma::map_single(ptr, 256, Direction::Bidirectional, mask).unwrap();
+        assert!(mapping.is_bounced());
+        assert!(mapping.bus_addr() + 0xff <= mask);
+        drop(mapping);
+        assert!(buf.iter().all(|&b| b == 0x5a));
+
+        let coherent = dma::alloc_coherent(100, u32::MAX as u64).unwrap();
+        assert_eq!(coherent.size(), mem::page_size());
+        let va = mem::VirtAddr::from(coherent.as_ptr());
+        let (_, flags) = mem::translate(va).unwrap();
+        assert_eq!(flags.cache, CacheSetting::NonCacheable);
+        unsafe {
+            assert_eq!(coherent.as_ptr().as_ptr().read_volatile(), 0);
+            coherent.as_ptr().as_ptr().write_volatile(0x42);
+            assert_eq!(coherent.as_ptr().as_ptr().read_volatile(), 0x42);
+        }
+        drop(coherent);
+        assert!(mem::translate(va).is_none());
+    }
+
+    #[test]
+    fn test_iommu_domain() {
+        use alloc::sync::Arc;
+        use core::sync::atomic::{AtomicU32, Ordering};
+        use hal_al::mmu::PageTableRef;
+        use mem::iommu::{self, IommuDomain, IommuError, IommuOps, StreamId};
+
+        /// 只记录绑定状态的 IOMMU
+        struct FakeIommu(AtomicU32);
+
+        impl IommuOps for FakeIommu {
+            fn attach(&self, stream: StreamId, _: PageTableRef, _: u16) -> Result<(), IommuError> {
+                self.0.store(stream, Ordering::SeqCst);
+                Ok(())
+            }
+            fn detach(&self, _: StreamId) {
+                self.0.store(0, Ordering::SeqCst);
+            }
+            fn flush_asid(&self, _: u16) {}
+        }
+
+        let fake = Arc::new(FakeIommu(AtomicU32::new(0)));
+        iommu::register(0xfff0, fake.clone());
+        assert!(matches!(
+            IommuDomain::new(0xfff1, 1),
+            Err(IommuError::NotFound(0xfff1))
+        ));
+
+        let domain = IommuDomain::new(0xfff0, 7).unwrap();
+        assert_eq!(fake.0.load(Ordering::SeqCst), 7);
+
+        let dev = driver::DeviceId::new();
+        iommu::attach_device(dev, domain.clone());
+
+        let mut buf = alloc::vec![0u8; 256];
+        let ptr = core::ptr::NonNull::new(buf.as_mut_ptr()).unwrap();
+        let mapping =
+            mem::dma::map_device(dev, ptr, buf.len(), mem::dma::Direction::ToDevice, u64::MAX)
+                .unwrap();
+        let bus = mapping.bus_addr();
+        assert!(iommu::IOVA_REGION.contains(&(bus as usize)));
+        let (pa, _) = mem::translate(mem::VirtAddr::from(ptr.as_ptr() as usize)).unwrap();
+        assert_eq!(domain.translate(bus), Some(pa));
+        drop(mapping);
+        assert_eq!(domain.translate(bus), None);
+
+        // 未登记的设备直接使用物理地址
+        assert!(Arc::ptr_eq(&iommu::detach_device(dev).unwrap(), &domain));
+        let mapping =
+            mem::dma::map_device(dev, ptr, buf.len(), mem::dma::Direction::ToDevice, u64::MAX)
+                .unwrap();
+        assert_eq!(mapping.bus_addr(), pa.raw() as u64);
+        drop(mapping);
+
+        drop(domain);
+        assert_eq!(fake.0.load(Ordering::SeqCst), 0);
+    }
+
+    /// 串口的中断线，串口本身未使能中断，只用来测试中断控制器
+    fn uart_irq() -> irq::IrqParam {
+        use platform::fdt::GetIrqConfig;
+
+        let fdt = match &global_val().platform_info {
+            PlatformInfoKind::DeviceTree(fdt) => fdt.get(),
+        };
+        let uart = fdt.find_compatible(&["arm,pl011"]).next().unwrap();
+        let info = uart.irq_info().unwrap();
+        irq::IrqParam {
+            intc: info.irq_parent,
+            cfg: info.cfgs[0].clone(),
+        }
+    }
+
+    #[test]
+    fn test_irq_affinity() {
+        use irq::{IrqError, IrqHandleResult};
+        use platform::{CPUId, cpu_current_id};
+
+        let param = uart_irq();
+        assert!(!param.cfg.is_private);
+
+        // 串口本身未使能中断，只检查控制器的配置
+        param
+            .register_builder(|_| IrqHandleResult::Handled)
+            .priority(0x80)
+            .register();
+        param.set_affinity(cpu_current_id()).unwrap();
+        assert!(matches!(
+            param.set_affinity(CPUId::from(usize::MAX)),
+            Err(IrqError::CpuOffline(_))
+        ));
+        irq::unregister_irq(param.cfg.irq);
+    }
+
+    #[test]
+    fn test_irq_shared_and_cascade() {
+        use alloc::sync::Arc;
+        use core::sync::atomic::{AtomicBool, Ordering};
+        use driver::{DeviceId, IrqId};
+        use hal_al::Trigger;
+        use irq::{CascadedIntc, IrqHandleResult, IrqParam};
+
+        /// 只记录子中断使能状态的控制器
+        struct FakeGpio(AtomicBool);
+
+        impl CascadedIntc for FakeGpio {
+            fn ack(&self) -> Option<IrqId> {
+                None
+            }
+            fn set_enable(&self, _: IrqId, enable: bool) {
+                self.0.store(enable, Ordering::SeqCst);
+            }
+            fn set_trigger(&self, _: IrqId, _: Trigger) {}
+        }
+
+        let line = uart_irq();
+
+        // 同一中断线上的两个处理函数可分别注销
+        let first = line.register_builder(|_| IrqHandleResult::None).register();
+        let second = line
+            .register_builder(|_| IrqHandleResult::Handled)
+            .register();
+        assert_ne!(first, second);
+        irq::unregister_handler(first);
+        irq::unregister_handler(second);
+
+        let gpio_id = DeviceId::new();
+        let gpio = Arc::new(FakeGpio(AtomicBool::new(false)));
+        let cascade = irq::register_cascade(gpio_id, &line, gpio.clone());
+        let cfg = gpio.parse_fdt(&[5, 8]).unwrap();
+        assert_eq!(cfg.irq, IrqId::from(5));
+        assert_eq!(cfg.trigger, Trigger::LevelLow);
+
+        let child = IrqParam { intc: gpio_id, cfg };
+        let handler = child
+            .register_builder(|_| IrqHandleResult::Handled)
+            .register();
+        assert!(gpio.0.load(Ordering::SeqCst));
+        assert!(matches!(
+            child.set_affinity(platform::cpu_current_id()),
+            Err(irq::IrqError::Cascaded(_))
+        ));
+        irq::unregister_handler(handler);
+        assert!(!gpio.0.load(Ordering::SeqCst));
+        irq::unregister_handler(cascade);
+    }
+
+    #[test]
+    fn test_softirq_and_tasklet() {
+        use alloc::sync::Arc;
+        use core::{
+            sync::atomic::{AtomicUsize, Ordering},
+            time::Duration,
+        };
+        use irq::softirq::{self, SoftirqError, Tasklet};
+
+        static SOFTIRQ_RUNS: AtomicUsize = AtomicUsize::new(0);
+        static DONE: sync::Event = sync::Event::new();
+
+        fn on_softirq() {
+            SOFTIRQ_RUNS.fetch_add(1, Ordering::SeqCst);
+        }
+
+        softirq::register_softirq(7, on_softirq).unwrap();
+        assert!(matches!(
+            softirq::register_softirq(7, on_softirq),
+            Err(SoftirqError::Busy(7))
+        ));
+
+        let runs = Arc::new(AtomicUsize::new(0));
+        let tasklet = Tasklet::new({
+            let runs = runs.clone();
+            move || {
+                runs.fetch_add(1, Ordering::SeqCst);
+                DONE.set();
+            }
+        });
+        softirq::raise_softirq(7);
+        // 执行前重复调度只执行一次
+        tasklet.schedule();
+        tasklet.schedule();
+
+        // 在下一次中断返回时执行
+        assert!(DONE.wait_timeout(Duration::from_secs(1)));
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+        assert_eq!(SOFTIRQ_RUNS.load(Ordering::SeqCst), 1);
+        assert!(!tasklet.is_scheduled());
+        softirq::unregister_softirq(7);
+    }
+
+    #[test]
+    fn test_ipi() {
+        use core::{
+            sync::atomic::{AtomicUsize, Ordering},
+            time::Duration,
+        };
+        use irq::ipi::{self, IpiError, IpiKind};
+        use platform::{CPUId, cpu_current_id};
+
+        static CALLS: AtomicUsize = AtomicUsize::new(0);
+        static FIRED: sync::Event = sync::Event::new();
+
+        let cpu = cpu_current_id();
+        ipi::call_on(
+            cpu,
+            || {
+                CALLS.fetch_add(1, Ordering::SeqCst);
+            },
+            true,
+        )
+        .unwrap();
+        assert_eq!(CALLS.load(Ordering::SeqCst), 1);
+
+        ipi::call_on_all(
+            || {
+                CALLS.fetch_add(1, Ordering::SeqCst);
+            },
+            true,
+        )
+        .unwrap();
+        assert_eq!(
+            CALLS.load(Ordering::SeqCst),
+            1 + platform::cpu_online_list().len()
+        );
+
+        // 发给自己的 IPI 同样经过中断控制器
+        ipi::send(cpu, IpiKind::Reschedule).unwrap();
+        assert!(matches!(
+            ipi::send(CPUId::from(usize::MAX), IpiKind::Call),
+            Err(IpiError::CpuOffline(_))
+        ));
+
+        time::after_on(cpu, Duration::from_millis(10), || FIRED.set()).unwrap();
+        assert!(FIRED.wait_timeout(Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn test_irq_stats() {
+        use alloc::string::ToString;
+        use core::time::Duration;
+        use driver::IrqId;
+        use irq::ipi::{self, IpiKind};
+
+        // IPI 与串口使用同一个中断控制器
+        let intc = uart_irq().intc;
+        let sgi = IrqId::from(IpiKind::Reschedule);
+        let count = || {
+            irq::stats()
+                .line(intc, sgi)
+                .map_or(0, |line| line.total().count)
+        };
+
+        let before = count();
+        ipi::send(platform::cpu_current_id(), IpiKind::Reschedule).unwrap();
+        time::spin_delay(Duration::from_millis(10));
+        assert_eq!(count(), before + 1);
+
+        let stats = irq::stats();
+        assert_eq!(stats.cpus.len(), stats.spurious.len());
+        let total = stats.line(intc, sgi).unwrap().total();
+        assert!(total.max >= total.average());
+
+        let table = stats.to_string();
+        println!("{table}");
+        assert!(table.contains("CPU0"));
+        assert!(table.contains("SPU"));
+    }
+
+    #[test]
+    fn test_timer_cancel() {
+        use core::{
+            sync::atomic::{AtomicBool, Ordering},
+            time::Duration,
+        };
+
+        static FIRED: AtomicBool = AtomicBool::new(false);
+
+        let timer = time::after(Duration::from_millis(10), || {
+            FIRED.store(true, Ordering::SeqCst)
+        });
+        timer.cancel();
+        assert!(timer.is_cancelled());
+        time::spin_delay(Duration::from_millis(30));
+        assert!(!FIRED.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_threaded_irq_rejected() {
+        use driver::IrqId;
+        use hal_al::Trigger;
+        use irq::{IrqError, IrqParam};
+
+        let sgi = IrqParam {
+            intc: uart_irq().intc,
+            cfg: driver::IrqConfig {
+                irq: IrqId::from(15),
+                trigger: Trigger::EdgeRising,
+                is_private: true,
+            },
+        };
+
+        // 失败时不注册处理函数，也不创建任务
+        assert!(matches!(
+            sgi.register_threaded(|_| {}),
+            Err(IrqError::Private(_))
+        ));
+    }
+
+    #[test]
+    fn test_threaded_irq_runs() {
+        use alloc::sync::Arc;
+        use core::{
+            sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+            time::Duration,
+        };
+        use driver::{DeviceId, IrqConfig, IrqId};
+        use hal_al::Trigger;
+        use irq::{CascadedIntc, IrqParam};
+        use platform::{CPUHardId, cpu_current_id};
+
+        /// 由 SGI 触发的控制器，每次只挂起一个子中断
+        struct FakeIntc {
+            pending: AtomicBool,
+            enabled: AtomicBool,
+        }
+
+        impl CascadedIntc for FakeIntc {
+            fn ack(&self) -> Option<IrqId> {
+                self.pending
+                    .swap(false, Ordering::SeqCst)
+                    .then(|| IrqId::from(3))
+            }
+            fn set_enable(&self, _: IrqId, enable: bool) {
+                self.enabled.store(enable, Ordering::SeqCst);
+            }
+            fn set_trigger(&self, _: IrqId, _: Trigger) {}
+        }
+
+        static RAN: sync::Event = sync::Event::new();
+
+        let sgi = IrqParam {
+            intc: uart_irq().intc,
+            cfg: IrqConfig {
+                irq: IrqId::from(14),
+                trigger: Trigger::EdgeRising,
+                is_private: true,
+            },
+        };
+        let fake = Arc::new(FakeIntc {
+            pending: AtomicBool::new(false),
+            enabled: AtomicBool::new(false),
+        });
+        let fake_id = DeviceId::new();
+        let cascade = irq::register_cascade(fake_id, &sgi, fake.clone());
+
+        let child = IrqParam {
+            intc: fake_id,
+            cfg: IrqConfig {
+                irq: IrqId::from(3),
+                trigger: Trigger::LevelHigh,
+                is_private: false,
+            },
+        };
+        let runs = Arc::new(AtomicUsize::new(0));
+        child
+            .register_threaded({
+                let runs = runs.clone();
+                move |_| {
+                    runs.fetch_add(1, Ordering::SeqCst);
+                    RAN.set();
+                }
+            })
+            .unwrap();
+        assert!(fake.enabled.load(Ordering::SeqCst));
+
+        fake.pending.store(true, Ordering::SeqCst);
+        platform::irq_send_sgi(
+            sgi.intc,
+            sgi.cfg.irq,
+            CPUHardId::from(cpu_current_id()).into(),
+        );
+        assert!(RAN.wait_timeout(Duration::from_secs(1)));
+
+        // 任务执行完毕后重新使能中断线
+        let deadline = time::since_boot() + Duration::from_secs(1);
+        while !fake.enabled.load(Ordering::SeqCst) {
+            assert!(time::since_boot() < deadline, "line not re-enabled");
+            time::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+
+        // 注销整条中断线时结束任务并禁用中断线
+        irq::unregister_irq(child.cfg.irq);
+        assert!(!fake.enabled.load(Ordering::SeqCst));
+        irq::unregister_handler(cascade);
+    }
 }
@@ -36,6 +36,25 @@ pub struct TestCase {
     pub test_fn: fn(),
 }
 
+/// 运行 `f`，结束时当前任务在其中分配的内存若未全部释放则打印泄漏的分配并 panic
+#[cfg(feature = "alloc-track")]
+pub fn assert_no_leak<R>(f: impl FnOnce() -> R) -> R {
+    use sparreal_kernel::mem::track;
+
+    let before = track::snapshot();
+    let ret = f();
+    let diff = before.diff(&track::snapshot());
+
+    let pid = sparreal_kernel::task::current().pid;
+    let mut leaked = 0;
+    for r in diff.allocated_by(pid) {
+        println!("leaked: {r:?}");
+        leaked += 1;
+    }
+    assert!(leaked == 0, "{leaked} allocations leaked");
+    ret
+}
+
 fn test_case_list() -> test_case::Iter<'static> {
     unsafe extern "C" {
         fn _stest_case();
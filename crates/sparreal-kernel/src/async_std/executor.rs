@@ -0,0 +1,183 @@
+use core::{
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicBool, Ordering},
+    task::{Context, Poll, Waker},
+};
+
+use alloc::{boxed::Box, collections::vec_deque::VecDeque, sync::Arc, task::Wake};
+use spin::{Mutex, Once};
+
+use crate::{
+    irq::NoIrqGuard,
+    sync::WaitQueue,
+    task::{self, Pid, TaskConfig},
+};
+
+type BoxFuture = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+const EXECUTOR_STACK_SIZE: usize = 64 * 1024;
+
+static READY: Mutex<VecDeque<Arc<AsyncTask>>> = Mutex::new(VecDeque::new());
+static READY_WQ: WaitQueue = WaitQueue::new();
+static EXECUTOR: Once = Once::new();
+
+struct AsyncTask {
+    future: Mutex<Option<BoxFuture>>,
+    queued: AtomicBool,
+}
+
+impl Wake for AsyncTask {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    /// 可在中断上下文中调用
+    fn wake_by_ref(self: &Arc<Self>) {
+        if self.queued.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        {
+            let _g = NoIrqGuard::new();
+            READY.lock().push_back(self.clone());
+        }
+        READY_WQ.notify_one();
+    }
+}
+
+fn ready_pop() -> Option<Arc<AsyncTask>> {
+    let _g = NoIrqGuard::new();
+    READY.lock().pop_front()
+}
+
+/// 执行器任务，轮询被唤醒的 future，没有时挂起
+fn executor_loop() {
+    loop {
+        READY_WQ.wait_until(|| {
+            let _g = NoIrqGuard::new();
+            !READY.lock().is_empty()
+        });
+
+        while let Some(t) = ready_pop() {
+            t.queued.store(false, Ordering::Release);
+            let waker = Waker::from(t.clone());
+            let mut cx = Context::from_waker(&waker);
+
+            let mut slot = t.future.lock();
+            if let Some(fut) = slot.as_mut()
+                && fut.as_mut().poll(&mut cx).is_ready()
+            {
+                slot.take();
+            }
+        }
+    }
+}
+
+struct JoinState<T> {
+    result: Option<T>,
+    waker: Option<Waker>,
+}
+
+/// [`spawn`] 返回的句柄，`await` 得到 future 的结果
+pub struct JoinHandle<T> {
+    state: Arc<Mutex<JoinState<T>>>,
+}
+
+impl<T> JoinHandle<T> {
+    pub fn is_finished(&self) -> bool {
+        self.state.lock().result.is_some()
+    }
+}
+
+impl<T> Future for JoinHandle<T> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = self.state.lock();
+        match state.result.take() {
+            Some(result) => Poll::Ready(result),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// 在内核执行器任务上运行 future
+pub fn spawn<F>(future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    EXECUTOR.call_once(|| {
+        let mut config = TaskConfig::new("async-executor");
+        config.stack_size = EXECUTOR_STACK_SIZE;
+        task::spawn_with_config(executor_loop, config).expect("async executor no memory");
+    });
+
+    let state = Arc::new(Mutex::new(JoinState {
+        result: None,
+        waker: None,
+    }));
+
+    let future: BoxFuture = Box::pin({
+        let state = state.clone();
+        async move {
+            let result = future.await;
+            let waker = {
+                let mut state = state.lock();
+                state.result = Some(result);
+                state.waker.take()
+            };
+            if let Some(waker) = waker {
+                waker.wake();
+            }
+        }
+    });
+
+    let t = Arc::new(AsyncTask {
+        future: Mutex::new(Some(future)),
+        queued: AtomicBool::new(false),
+    });
+    t.wake_by_ref();
+
+    JoinHandle { state }
+}
+
+/// 唤醒 [`block_on`] 所在的任务
+struct TaskWaker {
+    pid: Pid,
+    woken: AtomicBool,
+}
+
+impl Wake for TaskWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.woken.store(true, Ordering::Release);
+        task::wake_up_in_irq(self.pid);
+    }
+}
+
+/// 在当前任务中运行 future 直到完成，等待期间挂起当前任务
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = core::pin::pin!(future);
+    let state = Arc::new(TaskWaker {
+        pid: task::current().pid,
+        woken: AtomicBool::new(false),
+    });
+    let waker = Waker::from(state.clone());
+    let mut cx = Context::from_waker(&waker);
+
+    loop {
+        if let Poll::Ready(out) = future.as_mut().poll(&mut cx) {
+            return out;
+        }
+        while !state.woken.swap(false, Ordering::AcqRel) {
+            task::suspend();
+        }
+    }
+}
@@ -0,0 +1,95 @@
+use core::{
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicUsize, Ordering},
+    task::{Context, Poll, Waker},
+};
+
+use alloc::sync::Arc;
+use rdrive::IrqId;
+use spin::Mutex;
+
+use crate::irq::{IrqHandleResult, IrqHandlerId, IrqParam, NoIrqGuard, unregister_handler};
+
+struct Shared {
+    fired: AtomicUsize,
+    waker: Mutex<Option<Waker>>,
+}
+
+/// 等待中断触发的 future。
+///
+/// 创建时注册中断处理函数，每次 `await` 等待下一次中断，drop 时注销。
+/// 电平触发的中断需要通过 [`IrqFuture::with_handler`] 在中断上下文中清除中断源。
+pub struct IrqFuture {
+    irq: IrqId,
+    handler: IrqHandlerId,
+    seen: usize,
+    shared: Arc<Shared>,
+}
+
+impl IrqFuture {
+    pub fn new(param: &IrqParam) -> Self {
+        Self::with_handler(param, |_| IrqHandleResult::Handled)
+    }
+
+    /// `top_half` 在中断上下文中执行，返回 `Handled` 时唤醒等待者
+    pub fn with_handler(
+        param: &IrqParam,
+        top_half: impl Fn(IrqId) -> IrqHandleResult + 'static,
+    ) -> Self {
+        let shared = Arc::new(Shared {
+            fired: AtomicUsize::new(0),
+            waker: Mutex::new(None),
+        });
+
+        let handler = param
+            .register_builder({
+                let shared = shared.clone();
+                move |irq| {
+                    let res = top_half(irq);
+                    if let IrqHandleResult::Handled = res {
+                        shared.fired.fetch_add(1, Ordering::AcqRel);
+                        if let Some(waker) = shared.waker.lock().take() {
+                            waker.wake();
+                        }
+                    }
+                    res
+                }
+            })
+            .register();
+
+        Self {
+            irq: param.cfg.irq,
+            handler,
+            seen: 0,
+            shared,
+        }
+    }
+
+    pub fn irq(&self) -> IrqId {
+        self.irq
+    }
+}
+
+impl Future for IrqFuture {
+    type Output = IrqId;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        {
+            let _g = NoIrqGuard::new();
+            *self.shared.waker.lock() = Some(cx.waker().clone());
+        }
+        let fired = self.shared.fired.load(Ordering::Acquire);
+        if fired != self.seen {
+            self.seen = fired;
+            return Poll::Ready(self.irq);
+        }
+        Poll::Pending
+    }
+}
+
+impl Drop for IrqFuture {
+    fn drop(&mut self) {
+        unregister_handler(self.handler);
+    }
+}
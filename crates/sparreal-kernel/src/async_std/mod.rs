@@ -1 +1,6 @@
+mod executor;
+mod irq;
 pub mod time;
+
+pub use executor::{JoinHandle, block_on, spawn};
+pub use irq::IrqFuture;
@@ -1,32 +1,41 @@
 use core::future::Future;
+use core::task::Waker;
 use core::time::Duration;
 
-use crate::time::since_boot;
+use crate::time::{after, since_boot};
 
 pub fn sleep(duration: Duration) -> FutureSleep {
     let now = since_boot();
     FutureSleep {
         wake_at: now + duration,
+        waker: None,
     }
 }
 
 pub struct FutureSleep {
     wake_at: Duration,
+    /// 已注册到定时器的 waker
+    waker: Option<Waker>,
 }
 
 impl Future for FutureSleep {
     type Output = ();
 
     fn poll(
-        self: core::pin::Pin<&mut Self>,
+        mut self: core::pin::Pin<&mut Self>,
         cx: &mut core::task::Context<'_>,
     ) -> core::task::Poll<Self::Output> {
         let now = since_boot();
         if now >= self.wake_at {
-            core::task::Poll::Ready(())
-        } else {
-            cx.waker().wake_by_ref();
-            core::task::Poll::Pending
+            return core::task::Poll::Ready(());
         }
+
+        // waker 未变化时定时器已注册，无需重复注册
+        if !self.waker.as_ref().is_some_and(|w| w.will_wake(cx.waker())) {
+            let waker = cx.waker().clone();
+            self.waker = Some(waker.clone());
+            after(self.wake_at - now, move || waker.wake_by_ref());
+        }
+        core::task::Poll::Pending
     }
 }
@@ -1,17 +1,18 @@
 use core::{
-    alloc::Layout,
     fmt::Display,
+    mem::ManuallyDrop,
     ops::Range,
     sync::atomic::{AtomicBool, Ordering},
 };
 
-use alloc::{alloc::alloc, collections::btree_map::BTreeMap};
+use alloc::{alloc::alloc, collections::btree_map::BTreeMap, vec::Vec};
 use log::debug;
 
 use crate::{
-    irq,
-    mem::{PhysAddr, mmu::LINER_OFFSET, region::boot_regions},
+    irq::{self, ipi::CpuIpi, softirq::CpuSoftirq, stats::CpuIrqStats},
+    mem::{VirtAddr, region::boot_regions, slab::CpuCache, stack::KernelStack},
     platform::{self, CPUHardId, CPUId, cpu_hard_id, cpu_list, kstack_size, mmu::page_size},
+    task::RunQueue,
     time::TimerData,
 };
 
@@ -35,9 +36,23 @@ impl From<CPUId> for CPUHardId {
 }
 
 pub struct PerCPU {
+    pub id: CPUId,
     pub irq_chips: irq::CpuIrqChips,
     pub timer: TimerData,
-    pub stack: Range<PhysAddr>,
+    pub(crate) run_queue: RunQueue,
+    pub(crate) slab: CpuCache,
+    pub(crate) softirq: CpuSoftirq,
+    pub(crate) ipi: CpuIpi,
+    pub(crate) irq_stats: CpuIrqStats,
+    /// 内核栈 `[栈底, 栈顶)` 的虚拟地址，不含保护页
+    pub stack: Range<VirtAddr>,
+    online: AtomicBool,
+}
+
+impl PerCPU {
+    pub fn is_online(&self) -> bool {
+        self.online.load(Ordering::Acquire)
+    }
 }
 
 /// 初始化PerCPU
@@ -65,25 +80,36 @@ fn add_cpu(cpu: CPUHardId, idx: usize) {
     unsafe {
         let id = CPUId::from(idx);
 
-        let stack_bottom = if idx == 0 {
+        let stack = if idx == 0 {
             let region = platform::boot_regions()
                 .into_iter()
                 .find(|o| o.name().contains("stack0"))
                 .expect("stack region not found!");
-
-            region.range.start
+            let offset = platform::mmu::kimage_va_offset();
+            // 最低一页是保护页
+            let bottom = region.range.start.align_up(page_size()) + page_size();
+            VirtAddr::from(bottom.raw() + offset)..VirtAddr::from(region.range.end.raw() + offset)
         } else {
-            let stack =
-                alloc::alloc::alloc(Layout::from_size_align(kstack_size(), page_size()).unwrap());
-            PhysAddr::from(stack as usize - LINER_OFFSET)
+            // 在CPU整个生命周期内使用，不释放
+            let stack = ManuallyDrop::new(
+                KernelStack::new(kstack_size()).expect("no memory for CPU stack"),
+            );
+            VirtAddr::from(stack.bottom())..VirtAddr::from(stack.top())
         };
 
         (*PER_CPU.get()).insert(
             cpu,
             PerCPU {
+                id,
                 irq_chips: Default::default(),
                 timer: Default::default(),
-                stack: stack_bottom..stack_bottom + kstack_size(),
+                run_queue: Default::default(),
+                slab: Default::default(),
+                softirq: Default::default(),
+                ipi: Default::default(),
+                irq_stats: Default::default(),
+                stack,
+                online: AtomicBool::new(false),
             },
         );
         (*HARD_TO_SOFT.get()).insert(cpu, id);
@@ -118,3 +144,29 @@ pub fn cpu_global_meybeuninit() -> Option<&'static PerCPU> {
 pub fn cpu_inited() -> bool {
     IS_INITED.load(Ordering::SeqCst)
 }
+
+/// 标记当前CPU已上线
+pub(crate) fn set_current_cpu_online() {
+    cpu_global().online.store(true, Ordering::Release);
+}
+
+pub(crate) fn cpu_global_by_id(id: CPUId) -> Option<&'static PerCPU> {
+    if !cpu_inited() {
+        return None;
+    }
+    let hard = unsafe { *(*SOFT_TO_HARD.get()).get(&id)? };
+    unsafe { (*PER_CPU.get()).get(&hard) }
+}
+
+pub(crate) fn cpu_online_list() -> Vec<CPUId> {
+    if !cpu_inited() {
+        return Vec::new();
+    }
+    let mut list = unsafe { &*PER_CPU.get() }
+        .values()
+        .filter(|c| c.is_online())
+        .map(|c| c.id)
+        .collect::<Vec<_>>();
+    list.sort();
+    list
+}
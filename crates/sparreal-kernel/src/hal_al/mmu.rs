@@ -34,6 +34,15 @@ pub enum CacheSetting {
     PerCpu,
 }
 
+/// Attributes of a mapped page.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PageFlags {
+    pub access: AccessSetting,
+    pub cache: CacheSetting,
+    /// Accessible from user mode.
+    pub user: bool,
+}
+
 #[derive(Debug, Clone, Copy)]
 pub struct PageTableRef {
     pub id: usize,
@@ -48,14 +57,47 @@ pub trait Mmu {
     fn kimage_va_offset() -> usize;
 
     fn new_table(alloc: &mut dyn Access) -> Result<PageTableRef, PagingError>;
+    /// Free the root and all intermediate tables, mapped memory is not freed.
+    /// The table must no longer be installed on any CPU.
     fn release_table(table: PageTableRef, alloc: &mut dyn Access);
     fn get_kernel_table() -> PageTableRef;
     fn set_kernel_table(new_table: PageTableRef);
+    /// Mappings below the kernel half are non-global and tagged with the ASID
+    /// of the table they are activated with.
     fn table_map(
         table: PageTableRef,
         alloc: &mut dyn Access,
         config: &MapConfig,
     ) -> Result<(), PagingError>;
+    /// Unmap `[va_start, va_start + size)` and invalidate the TLB on all CPUs.
+    /// Unmapped pages in the range are skipped, intermediate tables are kept.
+    /// Block mappings partially covered by the range are split first.
+    fn table_unmap(
+        table: PageTableRef,
+        alloc: &mut dyn Access,
+        va_start: Virt<u8>,
+        size: usize,
+    ) -> Result<(), PagingError>;
+    /// Change the access permission of `[va_start, va_start + size)` and
+    /// invalidate the TLB on all CPUs. Unmapped pages in the range are skipped.
+    fn table_protect(
+        table: PageTableRef,
+        alloc: &mut dyn Access,
+        va_start: Virt<u8>,
+        size: usize,
+        access: AccessSetting,
+    ) -> Result<(), PagingError>;
+    /// Look up the physical address and attributes `va` is mapped to.
+    fn table_translate(
+        table: PageTableRef,
+        alloc: &mut dyn Access,
+        va: Virt<u8>,
+    ) -> Option<(Phys<u8>, PageFlags)>;
+    /// Install the user-half table of the current CPU, `table.id` is used as ASID.
+    /// `None` leaves the current CPU without a user half.
+    fn set_user_table(table: Option<PageTableRef>);
+    /// Invalidate the TLB entries tagged with `asid` on all CPUs.
+    fn flush_tlb_asid(asid: usize);
 }
 
 #[derive(Debug, Clone)]
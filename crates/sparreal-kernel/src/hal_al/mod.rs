@@ -1,3 +1,6 @@
+use core::ops::Range;
+
+pub use rdif_intc::Trigger;
 pub use rdrive::{DeviceId, IrqId, register::DriverRegisterSlice};
 
 pub use crate::irq::IrqParam;
@@ -55,8 +58,29 @@ pub trait Hal: mmu::Mmu {
     ///
     unsafe fn cpu_context_switch(prev_tcb: *mut u8, next_tcb: *mut u8);
 
+    /// 屏蔽中断后进入用户态，从 `pc` 开始执行，`arg` 为第一个参数。
+    ///
+    /// 此后从用户态陷入时使用 `kstack_top` 处的内核栈。
+    ///
+    /// # Safety
+    ///
+    /// 当前地址空间已映射 `pc` 与 `user_sp`，`kstack_top` 是当前任务的内核栈顶
+    unsafe fn user_enter(pc: usize, user_sp: usize, kstack_top: usize, arg: usize) -> !;
+
+    /// 回溯当前调用栈，将返回地址由内向外写入 `frames`，返回写入的个数。
+    ///
+    /// `stack` 为当前任务的内核栈，栈指针不在其中时不回溯。只访问栈指针与栈顶之间的内存，
+    /// 内核未保留帧指针时结果可能不完整。
+    fn backtrace(stack: Range<usize>, frames: &mut [usize]) -> usize;
+
     fn wait_for_interrupt();
 
+    /// Power on the secondary CPU `cpu_id`.
+    ///
+    /// The CPU starts from the platform secondary entry, which switches to the
+    /// stack given by [`run::secondary_stack_top`] and calls [`run::run_secondary`].
+    fn cpu_on(cpu_id: usize) -> Result<(), &'static str>;
+
     fn irq_init_current_cpu(id: DeviceId);
 
     fn irq_ack() -> IrqId;
@@ -68,6 +92,16 @@ pub trait Hal: mmu::Mmu {
 
     fn irq_enable(config: IrqParam);
     fn irq_disable(id: DeviceId, irq: IrqId);
+    /// 设置中断优先级，数值越小优先级越高，控制器未实现的低位被忽略。
+    ///
+    /// 私有中断只影响当前 CPU。
+    fn irq_set_priority(id: DeviceId, irq: IrqId, priority: u8);
+    /// 设置中断触发方式，私有中断只影响当前 CPU
+    fn irq_set_trigger(id: DeviceId, irq: IrqId, trigger: Trigger);
+    /// 将共享中断路由到硬件编号为 `cpu_hard_id` 的 CPU，可在中断使能时调用
+    fn irq_set_affinity(id: DeviceId, irq: IrqId, cpu_hard_id: usize);
+    /// 向硬件编号为 `cpu_hard_id` 的 CPU 发送软件生成中断，可在中断上下文中调用
+    fn irq_send_sgi(id: DeviceId, sgi: IrqId, cpu_hard_id: usize);
 
     fn shutdown() -> !;
     fn debug_put(b: u8);
@@ -1,14 +1,16 @@
+use core::time::Duration;
+
 use log::LevelFilter;
 
 use ansi_rgb::{Foreground, orange};
 
 use crate::{
     driver,
-    globals::{self, PlatformInfoKind, global_val},
+    globals::{self, PlatformInfoKind, cpu_global, global_val},
     io, irq,
     logger::KLogger,
-    platform::{self, app_main, platform_name, shutdown},
-    println, task,
+    platform::{self, app_main, cpu_hard_id, cpu_list, platform_name, shutdown},
+    println, task, time,
 };
 
 pub fn run(plat: PlatformInfoKind) {
@@ -28,20 +30,77 @@ pub fn run(plat: PlatformInfoKind) {
     unsafe { globals::setup_percpu() };
 
     print_start_msg();
-    
+
     driver::init();
     debug!("Driver initialized");
     task::init();
 
     irq::enable_all();
+    globals::set_current_cpu_online();
 
     driver::probe();
 
+    start_secondary_cpus();
+
     app_main();
 
     shutdown()
 }
 
+const CPU_ON_TIMEOUT: Duration = Duration::from_secs(1);
+
+fn start_secondary_cpus() {
+    let current = cpu_hard_id();
+
+    for cpu in cpu_list() {
+        if cpu.cpu_id == current {
+            continue;
+        }
+        let id = platform::CPUId::from(cpu.cpu_id);
+
+        debug!("Starting CPU {} ({})", id, cpu.cpu_id);
+        if let Err(e) = platform::cpu_on(cpu.cpu_id.into()) {
+            warn!("CPU {} start failed: {e}", cpu.cpu_id);
+            continue;
+        }
+
+        let deadline = time::since_boot() + CPU_ON_TIMEOUT;
+        while !platform::cpu_is_online(id) {
+            if time::since_boot() > deadline {
+                warn!("CPU {} start timeout", cpu.cpu_id);
+                break;
+            }
+            core::hint::spin_loop();
+        }
+    }
+
+    info!("CPUs online: {:?}", platform::cpu_online_list());
+}
+
+/// Top of the kernel stack allocated for the current CPU in [`globals::setup_percpu`].
+///
+/// The stack lives in the kernel stack region, which is only mapped by the
+/// kernel table, so the kernel table is installed on this CPU first.
+pub fn secondary_stack_top() -> usize {
+    crate::mem::mmu::table::install_kernel_table();
+    cpu_global().stack.end.raw()
+}
+
+/// Entry of secondary CPUs, called on the stack from [`secondary_stack_top`].
+pub fn run_secondary() -> ! {
+    irq::init_current_cpu();
+    time::init_current_cpu();
+    task::init_current_cpu();
+
+    irq::enable_all();
+    globals::set_current_cpu_online();
+    debug!("CPU {} online", cpu_hard_id());
+
+    loop {
+        platform::wait_for_interrupt();
+    }
+}
+
 macro_rules! print_pair {
     ($name:expr, $($arg:tt)*) => {
         $crate::print!("{:<30}: {}\r\n", $name, format_args!($($arg)*));
@@ -3,7 +3,7 @@ use core::fmt;
 use alloc::boxed::Box;
 use spin::Mutex;
 
-use crate::boot::debug;
+use crate::{boot::debug, irq::NoIrqGuard};
 
 static STDOUT: Mutex<Option<Box<dyn fmt::Write + Send>>> = Mutex::new(None);
 
@@ -12,6 +12,8 @@ pub fn stdout_use_debug() {
 }
 
 pub fn print(args: fmt::Arguments<'_>) {
+    // 中断上下文中也会打印，持锁期间不能被中断
+    let _irq = NoIrqGuard::new();
     let mut g = STDOUT.lock();
 
     if let Some(ref mut writer) = *g {
@@ -0,0 +1,77 @@
+//! 级联的中断控制器。
+//!
+//! GPIO 控制器等次级控制器的输出连接在上级控制器（如 GIC）的一条中断线上，
+//! 上级中断到来时由次级控制器找出挂起的子中断，再分发给子中断的处理函数。
+//! 子中断以次级控制器的 [`DeviceId`] 与其内部编号标识，注册方式与普通中断相同。
+
+use alloc::{collections::btree_map::BTreeMap, sync::Arc};
+use log::debug;
+use rdif_intc::Trigger;
+use rdrive::{DeviceId, IrqConfig, IrqId};
+use spin::Mutex;
+
+use super::{IrqHandleResult, IrqHandlerId, IrqParam, NoIrqGuard, SHARED, handle_line};
+
+static CASCADES: Mutex<BTreeMap<DeviceId, Arc<dyn CascadedIntc>>> = Mutex::new(BTreeMap::new());
+
+/// 次级中断控制器的驱动接口
+pub trait CascadedIntc: Send + Sync {
+    /// 取出一个挂起且使能的子中断并应答，没有时返回 `None`
+    fn ack(&self) -> Option<IrqId>;
+
+    /// 子中断的处理函数已返回，电平触发的中断此时应已清除中断源
+    fn eoi(&self, _irq: IrqId) {}
+
+    fn set_enable(&self, irq: IrqId, enable: bool);
+
+    fn set_trigger(&self, irq: IrqId, trigger: Trigger);
+
+    /// 解析设备树 `interrupts` 中的一项。
+    ///
+    /// 默认按 `#interrupt-cells = <2>` 解析，第二个 cell 为 `IRQ_TYPE_*` 标志。
+    fn parse_fdt(&self, cells: &[u32]) -> Option<IrqConfig> {
+        let trigger = match cells.get(1).copied().unwrap_or(0) & 0xf {
+            1 => Trigger::EdgeRising,
+            2 => Trigger::EdgeFailling,
+            3 => Trigger::EdgeBoth,
+            8 => Trigger::LevelLow,
+            _ => Trigger::LevelHigh,
+        };
+        Some(IrqConfig {
+            irq: (*cells.first()? as usize).into(),
+            trigger,
+            is_private: false,
+        })
+    }
+}
+
+/// 注册设备 `id` 为级联在 `parent` 上的控制器，并在 `parent` 上注册分发子中断的处理函数
+pub fn register_cascade(
+    id: DeviceId,
+    parent: &IrqParam,
+    intc: Arc<dyn CascadedIntc>,
+) -> IrqHandlerId {
+    {
+        let _g = NoIrqGuard::new();
+        CASCADES.lock().insert(id, intc.clone());
+    }
+    debug!("cascaded intc {id:?} on irq {:?}", parent.cfg.irq);
+
+    parent
+        .register_builder(move |_| {
+            let mut res = IrqHandleResult::None;
+            while let Some(irq) = intc.ack() {
+                let handlers = SHARED.get(id, irq);
+                handle_line(id, irq, handlers.as_deref().unwrap_or_default());
+                intc.eoi(irq);
+                res = IrqHandleResult::Handled;
+            }
+            res
+        })
+        .register()
+}
+
+pub(crate) fn get(id: DeviceId) -> Option<Arc<dyn CascadedIntc>> {
+    let _g = NoIrqGuard::new();
+    CASCADES.lock().get(&id).cloned()
+}
@@ -0,0 +1,220 @@
+//! 处理器间中断（IPI），基于中断控制器的软件生成中断（SGI）。
+//!
+//! 每种 [`IpiKind`] 占用一个 SGI 编号，在每个 CPU 初始化中断时注册为私有中断。
+//! 跨 CPU 调用的闭包放入目标 CPU 的队列，由其在中断上下文中执行。
+
+use core::{
+    hint::spin_loop,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use alloc::{boxed::Box, collections::vec_deque::VecDeque, sync::Arc, vec::Vec};
+use log::debug;
+use rdif_intc::{Intc, Trigger};
+use rdrive::{DeviceId, IrqConfig, IrqId};
+use spin::{Mutex, Once};
+
+use super::{IrqHandleResult, IrqParam, NoIrqGuard};
+use crate::{
+    globals::{cpu_global, cpu_global_by_id},
+    mem::mmu::aspace,
+    platform::{self, CPUHardId, CPUId, cpu_current_id},
+    task,
+};
+
+/// 发送 IPI 使用的中断控制器
+static IPI_INTC: Once<DeviceId> = Once::new();
+
+#[repr(usize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IpiKind {
+    /// 目标 CPU 从中断返回时重新调度
+    Reschedule = 0,
+    /// 执行 [`call_on`] 放入队列的闭包
+    Call = 1,
+}
+
+impl From<IpiKind> for IrqId {
+    fn from(value: IpiKind) -> Self {
+        (value as usize).into()
+    }
+}
+
+#[derive(Debug)]
+pub enum IpiError {
+    CpuOffline(CPUId),
+    /// 没有可发送 SGI 的中断控制器
+    NoIntc,
+}
+
+/// 每个CPU待执行的跨 CPU 调用
+#[derive(Default)]
+pub(crate) struct CpuIpi {
+    calls: Mutex<VecDeque<Arc<Call>>>,
+}
+
+struct Call {
+    func: Mutex<Option<Box<dyn FnOnce() + Send>>>,
+    done: AtomicBool,
+}
+
+impl Call {
+    fn new(f: impl FnOnce() + Send + 'static) -> Arc<Self> {
+        Arc::new(Self {
+            func: Mutex::new(Some(Box::new(f))),
+            done: AtomicBool::new(false),
+        })
+    }
+
+    fn run(&self) {
+        let f = self.func.lock().take();
+        if let Some(f) = f {
+            f();
+        }
+        self.done.store(true, Ordering::Release);
+    }
+
+    fn wait(&self) {
+        while !self.done.load(Ordering::Acquire) {
+            spin_loop();
+        }
+    }
+}
+
+/// 在当前 CPU 上注册各 IPI 的处理函数
+pub(crate) fn init_current_cpu() {
+    let Some(intc) = rdrive::get_list::<Intc>()
+        .first()
+        .map(|c| c.descriptor().device_id())
+    else {
+        return;
+    };
+    let intc = *IPI_INTC.call_once(|| intc);
+
+    for (kind, handler) in [
+        (
+            IpiKind::Reschedule,
+            handle_reschedule as fn(IrqId) -> IrqHandleResult,
+        ),
+        (IpiKind::Call, handle_call),
+    ] {
+        IrqParam {
+            intc,
+            cfg: IrqConfig {
+                irq: kind.into(),
+                trigger: Trigger::EdgeRising,
+                is_private: true,
+            },
+        }
+        .register_builder(handler)
+        .register();
+    }
+    debug!("ipi ready on cpu {}", cpu_current_id());
+}
+
+fn handle_reschedule(_irq: IrqId) -> IrqHandleResult {
+    // 中断返回路径上检查
+    task::set_need_resched();
+    IrqHandleResult::Handled
+}
+
+fn handle_call(_irq: IrqId) -> IrqHandleResult {
+    let calls = core::mem::take(&mut *cpu_global().ipi.calls.lock());
+    for call in calls {
+        call.run();
+    }
+    IrqHandleResult::Handled
+}
+
+/// 向 `cpu` 发送 IPI，可在中断上下文中调用
+pub fn send(cpu: CPUId, kind: IpiKind) -> Result<(), IpiError> {
+    if !platform::cpu_is_online(cpu) {
+        return Err(IpiError::CpuOffline(cpu));
+    }
+    let intc = *IPI_INTC.get().ok_or(IpiError::NoIntc)?;
+    platform::irq_send_sgi(intc, kind.into(), CPUHardId::from(cpu).into());
+    Ok(())
+}
+
+/// 在 `cpu` 上以中断上下文执行 `f`，`wait` 为 `true` 时等待其执行完毕。
+///
+/// `cpu` 为当前 CPU 时屏蔽中断后直接执行。
+///
+/// # Panics
+///
+/// 屏蔽中断时等待其他 CPU，两个 CPU 互相等待会死锁
+pub fn call_on(cpu: CPUId, f: impl FnOnce() + Send + 'static, wait: bool) -> Result<(), IpiError> {
+    if cpu == cpu_current_id() {
+        let _g = NoIrqGuard::new();
+        f();
+        return Ok(());
+    }
+    let call = Call::new(f);
+    enqueue(cpu, call.clone())?;
+    if wait {
+        assert!(
+            platform::irq_all_is_enabled(),
+            "wait for cpu {cpu} with irq disabled"
+        );
+        call.wait();
+    }
+    Ok(())
+}
+
+/// 在所有在线 CPU（包括当前 CPU）上执行 `f`，`wait` 含义同 [`call_on`]
+pub fn call_on_all(f: impl Fn() + Send + Sync + 'static, wait: bool) -> Result<(), IpiError> {
+    let f = Arc::new(f);
+    let current = cpu_current_id();
+    let mut calls = Vec::new();
+    for cpu in platform::cpu_online_list() {
+        if cpu == current {
+            continue;
+        }
+        let f = f.clone();
+        let call = Call::new(move || f());
+        enqueue(cpu, call.clone())?;
+        calls.push(call);
+    }
+    {
+        let _g = NoIrqGuard::new();
+        f();
+    }
+    if wait && !calls.is_empty() {
+        assert!(
+            platform::irq_all_is_enabled(),
+            "wait for other cpus with irq disabled"
+        );
+        for call in calls {
+            call.wait();
+        }
+    }
+    Ok(())
+}
+
+fn enqueue(cpu: CPUId, call: Arc<Call>) -> Result<(), IpiError> {
+    let target = match cpu_global_by_id(cpu) {
+        Some(c) if c.is_online() => c,
+        _ => return Err(IpiError::CpuOffline(cpu)),
+    };
+    {
+        let _g = NoIrqGuard::new();
+        target.ipi.calls.lock().push_back(call);
+    }
+    send(cpu, IpiKind::Call)
+}
+
+/// 让所有在线 CPU 重新加载当前任务的地址空间。
+///
+/// 释放页表前调用：TLBI 已广播到所有 CPU，但其他 CPU 的页表基址寄存器可能仍指向将被释放的页表。
+pub(crate) fn tlb_shootdown(wait: bool) {
+    let res = call_on_all(
+        || {
+            let aspace = task::try_current().and_then(|t| t.address_space.clone());
+            aspace::activate(aspace.as_deref());
+        },
+        wait,
+    );
+    if let Err(e) = res {
+        debug!("tlb shootdown: {e:?}");
+    }
+}
@@ -1,24 +1,38 @@
-use core::cell::UnsafeCell;
+use core::{
+    cell::UnsafeCell,
+    sync::atomic::{AtomicUsize, Ordering},
+};
 
-use alloc::{boxed::Box, collections::btree_map::BTreeMap, vec::Vec};
+use alloc::{boxed::Box, collections::btree_map::BTreeMap, sync::Arc, vec::Vec};
 use log::{debug, warn};
-use rdif_intc::Intc;
+use rdif_intc::{Intc, Trigger};
 pub use rdrive::Phandle;
 use rdrive::{DeviceId, IrqConfig, IrqId};
 use spin::Mutex;
 
+pub use cascade::{CascadedIntc, register_cascade};
+pub use stats::{IrqCounter, IrqStats, LineStats, stats};
+
 use crate::{
     globals::{self, cpu_global},
-    platform,
+    platform::{self, CPUHardId, CPUId, cpu_current_id},
+    task::TaskError,
+    time,
 };
 
+pub mod cascade;
+pub mod ipi;
+pub mod softirq;
+pub mod stats;
+mod threaded;
+
 #[derive(Default)]
 pub struct CpuIrqChips(BTreeMap<DeviceId, Chip>);
 
 pub struct Chip {
     mutex: Mutex<()>,
     // device: Box<dyn local::Interface>,
-    handlers: UnsafeCell<BTreeMap<IrqId, Box<IrqHandler>>>,
+    handlers: UnsafeCell<BTreeMap<IrqId, HandlerList>>,
 }
 
 unsafe impl Send for Chip {}
@@ -26,6 +40,11 @@ unsafe impl Sync for Chip {}
 
 pub type IrqHandler = dyn Fn(IrqId) -> IrqHandleResult;
 
+/// 最低可用的优先级，GIC 至少实现优先级的高 4 位，且 PMR 为 0xff 时屏蔽该级别
+pub const PRIORITY_LOWEST: u8 = 0xf0;
+/// 未指定优先级时使用，高于和低于它各留有余地
+pub const PRIORITY_DEFAULT: u8 = 0xa0;
+
 pub fn enable_all() {
     platform::irq_all_enable();
 }
@@ -78,11 +97,16 @@ pub(crate) fn init_current_cpu() {
         );
         // drop(g);
     }
+
+    ipi::init_current_cpu();
 }
 
 pub enum IrqHandleResult {
     Handled,
+    /// 不是本处理函数的中断源
     None,
+    /// 已应答，剩余工作交给 [`IrqRegister::threaded`] 指定的任务
+    WakeThread,
 }
 
 fn chip_cpu(id: DeviceId) -> &'static Chip {
@@ -97,86 +121,296 @@ pub struct IrqRegister {
     pub param: IrqParam,
     pub handler: Box<IrqHandler>,
     pub priority: Option<usize>,
+    pub trigger: Option<Trigger>,
+    pub affinity: Option<CPUId>,
+    pub thread: Option<Box<dyn Fn(IrqId) + Send>>,
 }
 
 impl IrqRegister {
-    pub fn register(self) {
+    /// 注册处理函数并使能中断。
+    ///
+    /// 同一中断线可注册多个处理函数，中断到来时依次调用，直到有一个返回 `Handled`。
+    /// 中断线的优先级、触发方式与亲和性由第一个注册者配置，之后的注册者只追加处理函数。
+    ///
+    /// # Panics
+    ///
+    /// 设置了 [`threaded`](Self::threaded) 且注册失败，需要处理错误时使用 [`try_register`](Self::try_register)
+    pub fn register(self) -> IrqHandlerId {
+        let irq = self.param.cfg.irq;
+        self.try_register()
+            .unwrap_or_else(|e| panic!("register irq {irq:?} failed: {e:?}"))
+    }
+
+    /// 同 [`register`](Self::register)，线程化中断的任务创建失败时不注册处理函数
+    pub fn try_register(mut self) -> Result<IrqHandlerId, IrqError> {
         let irq = self.param.cfg.irq;
         let irq_parent = self.param.intc;
+        let handler = IrqHandlerId {
+            intc: irq_parent,
+            irq,
+            id: NEXT_HANDLER_ID.fetch_add(1, Ordering::Relaxed),
+            is_private: self.param.cfg.is_private,
+        };
+        let trigger = self.trigger.unwrap_or(self.param.cfg.trigger);
+
+        if let Some(thread_fn) = self.thread.take() {
+            // 任务可能在其他 CPU 上执行，无法重新使能私有中断
+            if self.param.cfg.is_private {
+                return Err(IrqError::Private(irq));
+            }
+            let thread = threaded::spawn(&self.param, handler.id, thread_fn)
+                .map_err(IrqError::ThreadSpawn)?;
+            let hard = self.handler;
+            self.handler = Box::new(move |irq| match hard(irq) {
+                IrqHandleResult::WakeThread => {
+                    thread.wake();
+                    IrqHandleResult::Handled
+                }
+                res => res,
+            });
+        }
+
+        if let Some(cascade) = cascade::get(irq_parent) {
+            if SHARED.register_handle(irq_parent, irq, handler.id, self.handler) {
+                cascade.set_trigger(irq, trigger);
+                cascade.set_enable(irq, true);
+                debug!("Enable irq {irq:?} on cascaded chip {irq_parent:?}");
+            }
+            return Ok(handler);
+        }
+
+        let is_first = if self.param.cfg.is_private {
+            chip_cpu(irq_parent).register_handle(irq, handler.id, self.handler)
+        } else {
+            SHARED.register_handle(irq_parent, irq, handler.id, self.handler)
+        };
+        if !is_first {
+            debug!("Share irq {irq:?} on chip {irq_parent:?}");
+            return Ok(handler);
+        }
 
-        let chip = chip_cpu(irq_parent);
-        chip.register_handle(irq, self.handler);
+        let priority = self
+            .priority
+            .map_or(PRIORITY_DEFAULT, |p| p.min(PRIORITY_LOWEST as usize) as u8);
+        platform::irq_set_priority(irq_parent, irq, priority);
+        platform::irq_set_trigger(irq_parent, irq, trigger);
+        if !self.param.cfg.is_private {
+            let cpu = self.affinity.unwrap_or_else(cpu_current_id);
+            platform::irq_set_affinity(irq_parent, irq, CPUHardId::from(cpu).into());
+        }
 
         platform::irq_enable(self.param.clone());
 
-        // if self.param.cfg.is_private
-        //     && let local::Capability::ConfigLocalIrq(c) = chip.device.capability()
-        // {
-        //     if let Some(p) = self.priority {
-        //         c.set_priority(irq, p).unwrap();
-        //     } else {
-        //         c.set_priority(irq, 0).unwrap();
-        //     }
-        //     c.set_trigger(irq, self.param.cfg.trigger).unwrap();
-        //     c.irq_enable(irq).unwrap();
-        // } else {
-        //     let mut c = rdrive::get::<Intc>(irq_parent).unwrap().lock().unwrap();
-        //     if let Some(p) = self.priority {
-        //         c.set_priority(irq, p).unwrap();
-        //     } else {
-        //         c.set_priority(irq, 0).unwrap();
-        //     }
-        //     if !self.param.cfg.is_private {
-        //         c.set_target_cpu(irq, cpu_hard_id().into()).unwrap();
-        //     }
-        //     c.set_trigger(irq, self.param.cfg.trigger).unwrap();
-        //     c.irq_enable(irq).unwrap();
-        // }
-
-        debug!("Enable irq {irq:?} on chip {irq_parent:?}");
+        debug!("Enable irq {irq:?} on chip {irq_parent:?}, priority {priority:#x}");
+        Ok(handler)
     }
 
+    /// 数值越小优先级越高，超过 [`PRIORITY_LOWEST`] 时取 [`PRIORITY_LOWEST`]
     pub fn priority(mut self, priority: usize) -> Self {
         self.priority = Some(priority);
         self
     }
+
+    /// 覆盖设备树中的触发方式
+    pub fn trigger(mut self, trigger: Trigger) -> Self {
+        self.trigger = Some(trigger);
+        self
+    }
+
+    /// 共享中断交由 `cpu` 处理，默认为注册时所在的 CPU，私有中断忽略此设置
+    pub fn affinity(mut self, cpu: CPUId) -> Self {
+        self.affinity = Some(cpu);
+        self
+    }
+
+    /// 线程化处理：处理函数返回 [`IrqHandleResult::WakeThread`] 时屏蔽中断线，
+    /// 由高优先级的内核任务执行 `thread_fn`，返回后重新使能中断线
+    pub fn threaded(mut self, thread_fn: impl Fn(IrqId) + Send + 'static) -> Self {
+        self.thread = Some(Box::new(thread_fn));
+        self
+    }
+}
+
+/// [`IrqRegister::register`] 返回的标识，用于注销单个处理函数
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IrqHandlerId {
+    intc: DeviceId,
+    irq: IrqId,
+    id: usize,
+    is_private: bool,
+}
+
+static NEXT_HANDLER_ID: AtomicUsize = AtomicUsize::new(1);
+
+/// 同一中断线上的处理函数，按注册顺序调用。
+///
+/// 注册与注销时整体替换，中断上下文中只需复制外层的 `Arc`，不分配内存。
+type HandlerList = Arc<[(usize, Arc<IrqHandler>)]>;
+
+/// 在 `list` 末尾追加处理函数
+fn list_push(list: Option<&HandlerList>, id: usize, handle: Box<IrqHandler>) -> HandlerList {
+    list.into_iter()
+        .flat_map(|l| l.iter().cloned())
+        .chain(core::iter::once((id, Arc::from(handle))))
+        .collect()
+}
+
+/// 依次调用处理函数，直到有一个处理了中断
+fn dispatch(handlers: &[(usize, Arc<IrqHandler>)], irq: IrqId) -> IrqHandleResult {
+    for (_, handler) in handlers {
+        match handler(irq) {
+            IrqHandleResult::None => continue,
+            res => return res,
+        }
+    }
+    IrqHandleResult::None
+}
+
+/// 分发 `intc` 上的中断 `irq` 并记录统计
+fn handle_line(intc: DeviceId, irq: IrqId, handlers: &[(usize, Arc<IrqHandler>)]) {
+    if handlers.is_empty() {
+        warn!("IRQ {irq:?} on {intc:?} no handler");
+        stats::record_spurious();
+        return;
+    }
+    let start = time::since_boot();
+    let res = dispatch(handlers, irq);
+    stats::record(intc, irq, time::since_boot().saturating_sub(start));
+    if let IrqHandleResult::None = res {
+        warn!(
+            "IRQ {irq:?} on {intc:?} not handled by {} handlers",
+            handlers.len()
+        );
+        stats::record_spurious();
+    }
 }
 
 impl Chip {
-    fn register_handle(&self, irq: IrqId, handle: Box<IrqHandler>) {
+    /// 返回是否为该中断线的第一个处理函数
+    fn register_handle(&self, irq: IrqId, id: usize, handle: Box<IrqHandler>) -> bool {
         let g = NoIrqGuard::new();
         let gm = self.mutex.lock();
-        unsafe { &mut *self.handlers.get() }.insert(irq, handle);
+        let handlers = unsafe { &mut *self.handlers.get() };
+        let list = list_push(handlers.get(&irq), id, handle);
+        let is_first = list.len() == 1;
+        handlers.insert(irq, list);
         drop(gm);
         drop(g);
+        is_first
     }
 
-    fn unregister_handle(&self, irq: IrqId) {
+    /// 返回中断线是否已没有处理函数
+    fn unregister_handle(&self, irq: IrqId, id: usize) -> bool {
         let g = NoIrqGuard::new();
         let gm = self.mutex.lock();
-        unsafe { &mut *self.handlers.get() }.remove(&irq);
+        let handlers = unsafe { &mut *self.handlers.get() };
+        let is_empty = match handlers.get(&irq) {
+            Some(list) => {
+                let kept: HandlerList = list.iter().filter(|&&(i, _)| i != id).cloned().collect();
+                let is_empty = kept.is_empty();
+                if is_empty {
+                    handlers.remove(&irq);
+                } else {
+                    handlers.insert(irq, kept);
+                }
+                is_empty
+            }
+            None => false,
+        };
         drop(gm);
         drop(g);
+        is_empty
+    }
+
+    /// 注销中断线上的全部处理函数并返回
+    fn unregister_all(&self, irq: IrqId) -> Option<HandlerList> {
+        let _g = NoIrqGuard::new();
+        let _gm = self.mutex.lock();
+        unsafe { &mut *self.handlers.get() }.remove(&irq)
     }
 
-    fn handle_irq(&self) -> Option<()> {
+    fn handle_irq(&self, id: DeviceId) -> Option<()> {
         // let irq = self.device.ack()?;
         let irq = platform::irq_ack();
 
-        if let Some(handler) = unsafe { &mut *self.handlers.get() }.get(&irq) {
-            let res = (handler)(irq);
-            if let IrqHandleResult::None = res {
-                return Some(());
-            }
-        } else {
-            warn!("IRQ {irq:?} no handler");
-        }
+        // 共享中断可能在其他 CPU 上注销或迁移，取出后在锁外调用
+        let handlers = match unsafe { &*self.handlers.get() }.get(&irq) {
+            Some(list) => Some(list.clone()),
+            None => SHARED.get(id, irq),
+        };
+        handle_line(id, irq, handlers.as_deref().unwrap_or_default());
         // self.device.eoi(irq);
         platform::irq_eoi(irq);
         Some(())
     }
 }
 
+/// 共享中断与级联控制器子中断的处理函数，中断可被路由到任意 CPU
+struct SharedHandlers {
+    handlers: Mutex<BTreeMap<(DeviceId, IrqId), HandlerList>>,
+}
+
+unsafe impl Send for SharedHandlers {}
+unsafe impl Sync for SharedHandlers {}
+
+static SHARED: SharedHandlers = SharedHandlers {
+    handlers: Mutex::new(BTreeMap::new()),
+};
+
+impl SharedHandlers {
+    /// 返回是否为该中断线的第一个处理函数
+    fn register_handle(
+        &self,
+        intc: DeviceId,
+        irq: IrqId,
+        id: usize,
+        handle: Box<IrqHandler>,
+    ) -> bool {
+        let _g = NoIrqGuard::new();
+        let mut handlers = self.handlers.lock();
+        let list = list_push(handlers.get(&(intc, irq)), id, handle);
+        let is_first = list.len() == 1;
+        handlers.insert((intc, irq), list);
+        is_first
+    }
+
+    /// 注销 `intc` 上 `irq` 的处理函数 `id`，返回中断线是否已没有处理函数
+    fn unregister_handle(&self, intc: DeviceId, irq: IrqId, id: usize) -> bool {
+        let _g = NoIrqGuard::new();
+        let mut handlers = self.handlers.lock();
+        let Some(list) = handlers.get(&(intc, irq)) else {
+            return false;
+        };
+        let kept: HandlerList = list.iter().filter(|&&(i, _)| i != id).cloned().collect();
+        let is_empty = kept.is_empty();
+        if is_empty {
+            handlers.remove(&(intc, irq));
+        } else {
+            handlers.insert((intc, irq), kept);
+        }
+        is_empty
+    }
+
+    /// 注销各控制器上 `irq` 的全部处理函数并返回
+    fn unregister_all(&self, irq: IrqId) -> Vec<(DeviceId, HandlerList)> {
+        let _g = NoIrqGuard::new();
+        let mut handlers = self.handlers.lock();
+        let keys: Vec<_> = handlers
+            .keys()
+            .filter(|&&(_, i)| i == irq)
+            .copied()
+            .collect();
+        keys.into_iter()
+            .filter_map(|key| handlers.remove(&key).map(|list| (key.0, list)))
+            .collect()
+    }
+
+    fn get(&self, intc: DeviceId, irq: IrqId) -> Option<HandlerList> {
+        let _g = NoIrqGuard::new();
+        self.handlers.lock().get(&(intc, irq)).cloned()
+    }
+}
+
 pub struct NoIrqGuard {
     is_enabled: bool,
 }
@@ -204,10 +438,15 @@ impl Drop for NoIrqGuard {
 }
 
 pub fn handle_irq() -> usize {
-    for chip in cpu_global().irq_chips.0.values() {
-        chip.handle_irq();
+    for (&id, chip) in cpu_global().irq_chips.0.iter() {
+        chip.handle_irq(id);
     }
 
+    softirq::run_pending();
+
+    // 在中断返回路径上抢占，被切走的任务恢复后从这里返回
+    crate::task::schedule_if_needed();
+
     let cu = crate::task::current();
 
     cu.sp
@@ -234,12 +473,103 @@ impl IrqParam {
             param: self.clone(),
             handler: Box::new(handler),
             priority: None,
+            trigger: None,
+            affinity: None,
+            thread: None,
         }
     }
+
+    /// 注册线程化中断，中断上下文中只屏蔽中断线，全部工作由 `thread_fn` 完成
+    pub fn register_threaded(
+        &self,
+        thread_fn: impl Fn(IrqId) + Send + 'static,
+    ) -> Result<IrqHandlerId, IrqError> {
+        self.register_builder(|_| IrqHandleResult::WakeThread)
+            .threaded(thread_fn)
+            .try_register()
+    }
+
+    /// 将共享中断迁移到 `cpu`，之后的中断由其处理
+    pub fn set_affinity(&self, cpu: CPUId) -> Result<(), IrqError> {
+        if self.cfg.is_private {
+            return Err(IrqError::Private(self.cfg.irq));
+        }
+        if cascade::get(self.intc).is_some() {
+            return Err(IrqError::Cascaded(self.cfg.irq));
+        }
+        if !platform::cpu_is_online(cpu) {
+            return Err(IrqError::CpuOffline(cpu));
+        }
+        platform::irq_set_affinity(self.intc, self.cfg.irq, CPUHardId::from(cpu).into());
+        debug!("irq {:?} routed to cpu {cpu}", self.cfg.irq);
+        Ok(())
+    }
+
+    /// 运行时修改优先级，私有中断只影响当前 CPU。
+    ///
+    /// 级联控制器的子中断使用其上级中断线的优先级，忽略此设置。
+    pub fn set_priority(&self, priority: u8) {
+        if cascade::get(self.intc).is_some() {
+            return;
+        }
+        platform::irq_set_priority(self.intc, self.cfg.irq, priority.min(PRIORITY_LOWEST));
+    }
 }
 
+#[derive(Debug)]
+pub enum IrqError {
+    /// 私有中断只能由所属 CPU 处理
+    Private(IrqId),
+    /// 级联控制器的子中断随其上级中断线路由
+    Cascaded(IrqId),
+    CpuOffline(CPUId),
+    /// 线程化中断的任务创建失败
+    ThreadSpawn(TaskError),
+}
+
+/// 注销当前 CPU 与共享中断中 `irq` 的全部处理函数，结束其线程化任务并禁用中断线
 pub fn unregister_irq(irq: IrqId) {
-    for chip in cpu_global().irq_chips.0.values() {
-        chip.unregister_handle(irq);
+    let mut removed: Vec<_> = cpu_global()
+        .irq_chips
+        .0
+        .iter()
+        .filter_map(|(&intc, chip)| chip.unregister_all(irq).map(|list| (intc, list)))
+        .collect();
+    removed.extend(SHARED.unregister_all(irq));
+
+    for (intc, list) in removed {
+        for &(id, _) in list.iter() {
+            threaded::stop(id, false);
+        }
+        disable_line(intc, irq);
+    }
+}
+
+fn disable_line(intc: DeviceId, irq: IrqId) {
+    match cascade::get(intc) {
+        Some(cascade) => cascade.set_enable(irq, false),
+        None => platform::irq_disable(intc, irq),
+    }
+    debug!("Disable irq {irq:?} on chip {intc:?}");
+}
+
+/// 注销单个处理函数，中断线上没有其他处理函数时禁用该中断。
+///
+/// 私有中断须在注册时所在的 CPU 上注销。
+pub fn unregister_handler(handler: IrqHandlerId) {
+    let IrqHandlerId {
+        intc,
+        irq,
+        id,
+        is_private,
+    } = handler;
+    let is_empty = if is_private && cascade::get(intc).is_none() {
+        chip_cpu(intc).unregister_handle(irq, id)
+    } else {
+        SHARED.unregister_handle(intc, irq, id)
+    };
+    threaded::stop(id, !is_empty);
+    if is_empty {
+        disable_line(intc, irq);
     }
 }
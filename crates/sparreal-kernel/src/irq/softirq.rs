@@ -0,0 +1,158 @@
+//! 中断下半部：softirq 与 tasklet。
+//!
+//! 中断处理函数只做必要的应答，其余工作通过 [`raise_softirq`] 或 [`Tasklet::schedule`]
+//! 推迟到当前 CPU 从中断返回之前执行。此时中断控制器已完成 EOI，但 CPU 仍屏蔽中断，
+//! 因此每次最多处理 [`MAX_ROUNDS`] 轮，剩余的留到下一次中断返回时处理。
+//! 在任务上下文中触发的工作同样在下一次中断返回时执行。
+
+use core::sync::atomic::{AtomicU8, AtomicU32, Ordering};
+
+use alloc::{boxed::Box, collections::vec_deque::VecDeque, sync::Arc, vec::Vec};
+use spin::Mutex;
+
+use crate::{globals::cpu_global_meybeuninit, irq::NoIrqGuard};
+
+/// softirq 编号的个数
+pub const SOFTIRQ_VECTORS: usize = 16;
+/// 每次中断返回时最多处理的轮数
+pub const MAX_ROUNDS: usize = 8;
+
+/// softirq 处理函数，在中断返回前执行，不能阻塞
+pub type SoftirqHandler = fn();
+
+static HANDLERS: Mutex<[Option<SoftirqHandler>; SOFTIRQ_VECTORS]> =
+    Mutex::new([None; SOFTIRQ_VECTORS]);
+
+#[derive(Debug)]
+pub enum SoftirqError {
+    InvalidVector(usize),
+    /// 该编号已注册处理函数
+    Busy(usize),
+}
+
+/// 每个CPU待处理的 softirq 与 tasklet
+#[derive(Default)]
+pub(crate) struct CpuSoftirq {
+    pending: AtomicU32,
+    tasklets: Mutex<VecDeque<Arc<Tasklet>>>,
+}
+
+/// 注册编号为 `nr` 的 softirq 处理函数，同一编号可同时在多个CPU上执行
+pub fn register_softirq(nr: usize, handler: SoftirqHandler) -> Result<(), SoftirqError> {
+    if nr >= SOFTIRQ_VECTORS {
+        return Err(SoftirqError::InvalidVector(nr));
+    }
+    let _g = NoIrqGuard::new();
+    let mut handlers = HANDLERS.lock();
+    if handlers[nr].is_some() {
+        return Err(SoftirqError::Busy(nr));
+    }
+    handlers[nr] = Some(handler);
+    Ok(())
+}
+
+pub fn unregister_softirq(nr: usize) {
+    let _g = NoIrqGuard::new();
+    if let Some(h) = HANDLERS.lock().get_mut(nr) {
+        *h = None;
+    }
+}
+
+/// 在当前CPU上触发编号为 `nr` 的 softirq，可在中断上下文中调用
+pub fn raise_softirq(nr: usize) {
+    assert!(nr < SOFTIRQ_VECTORS, "invalid softirq {nr}");
+    let Some(cpu) = cpu_global_meybeuninit() else {
+        return;
+    };
+    cpu.softirq.pending.fetch_or(1 << nr, Ordering::Release);
+}
+
+const TASKLET_SCHEDULED: u8 = 1 << 0;
+const TASKLET_RUNNING: u8 = 1 << 1;
+
+/// 可多次调度的延迟工作。
+///
+/// 执行前多次调度只执行一次；同一 tasklet 不会在多个CPU上同时执行。
+pub struct Tasklet {
+    func: Box<dyn Fn() + Send + Sync>,
+    state: AtomicU8,
+}
+
+impl Tasklet {
+    pub fn new(func: impl Fn() + Send + Sync + 'static) -> Arc<Self> {
+        Arc::new(Self {
+            func: Box::new(func),
+            state: AtomicU8::new(0),
+        })
+    }
+
+    /// 在当前CPU上调度执行，已调度未执行时忽略，可在中断上下文中调用
+    pub fn schedule(self: &Arc<Self>) {
+        if self.state.fetch_or(TASKLET_SCHEDULED, Ordering::AcqRel) & TASKLET_SCHEDULED != 0 {
+            return;
+        }
+        let Some(cpu) = cpu_global_meybeuninit() else {
+            self.state.fetch_and(!TASKLET_SCHEDULED, Ordering::Release);
+            return;
+        };
+        let _g = NoIrqGuard::new();
+        cpu.softirq.tasklets.lock().push_back(self.clone());
+    }
+
+    pub fn is_scheduled(&self) -> bool {
+        self.state.load(Ordering::Acquire) & TASKLET_SCHEDULED != 0
+    }
+
+    /// 执行，正在其他CPU上执行时返回 `false`
+    fn run(&self) -> bool {
+        if self.state.fetch_or(TASKLET_RUNNING, Ordering::Acquire) & TASKLET_RUNNING != 0 {
+            return false;
+        }
+        // 先清除调度标志，执行期间可以再次调度
+        self.state.fetch_and(!TASKLET_SCHEDULED, Ordering::AcqRel);
+        (self.func)();
+        self.state.fetch_and(!TASKLET_RUNNING, Ordering::Release);
+        true
+    }
+}
+
+/// 当前CPU是否有待处理的工作
+pub fn has_pending() -> bool {
+    cpu_global_meybeuninit().is_some_and(|cpu| {
+        cpu.softirq.pending.load(Ordering::Acquire) != 0 || {
+            let _g = NoIrqGuard::new();
+            !cpu.softirq.tasklets.lock().is_empty()
+        }
+    })
+}
+
+/// 在中断返回前调用，屏蔽中断
+pub(crate) fn run_pending() {
+    let Some(cpu) = cpu_global_meybeuninit() else {
+        return;
+    };
+
+    for _ in 0..MAX_ROUNDS {
+        let pending = cpu.softirq.pending.swap(0, Ordering::AcqRel);
+        if pending != 0 {
+            let handlers = *HANDLERS.lock();
+            for (nr, handler) in handlers.iter().enumerate() {
+                if pending & (1 << nr) != 0
+                    && let Some(handler) = handler
+                {
+                    handler();
+                }
+            }
+        }
+
+        let tasklets = core::mem::take(&mut *cpu.softirq.tasklets.lock());
+        if pending == 0 && tasklets.is_empty() {
+            return;
+        }
+        let busy: Vec<_> = tasklets.into_iter().filter(|t| !t.run()).collect();
+        if !busy.is_empty() {
+            // 留到下一轮，届时其他CPU应已执行完毕
+            cpu.softirq.tasklets.lock().extend(busy);
+        }
+    }
+}
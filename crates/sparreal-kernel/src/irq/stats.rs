@@ -0,0 +1,172 @@
+//! 中断统计，按 CPU 与中断线记录次数与处理耗时。
+//!
+//! 计数只由所属 CPU 在中断上下文中更新，[`stats`] 读取时取得各 CPU 的快照。
+
+use core::{
+    fmt::{self, Display},
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use alloc::{collections::btree_map::BTreeMap, format, vec, vec::Vec};
+use rdrive::{DeviceId, IrqId};
+use spin::Mutex;
+
+use super::NoIrqGuard;
+use crate::{
+    globals::{cpu_global_by_id, cpu_global_meybeuninit},
+    platform::{self, CPUId},
+};
+
+/// 每个CPU的中断统计
+#[derive(Default)]
+pub(crate) struct CpuIrqStats {
+    lines: Mutex<BTreeMap<(DeviceId, IrqId), IrqCounter>>,
+    /// 没有处理函数或处理函数均未处理的中断
+    spurious: AtomicU64,
+}
+
+/// 一条中断线在一个CPU上的统计
+#[derive(Debug, Default, Clone, Copy)]
+pub struct IrqCounter {
+    pub count: u64,
+    /// 处理函数的总耗时
+    pub total: Duration,
+    /// 单次处理的最大耗时
+    pub max: Duration,
+}
+
+impl IrqCounter {
+    fn add(&mut self, other: &IrqCounter) {
+        self.count += other.count;
+        self.total += other.total;
+        self.max = self.max.max(other.max);
+    }
+
+    /// 平均每次处理的耗时
+    pub fn average(&self) -> Duration {
+        if self.count == 0 {
+            return Duration::ZERO;
+        }
+        Duration::from_nanos((self.total.as_nanos() / self.count as u128) as u64)
+    }
+}
+
+/// 一条中断线在各CPU上的统计，顺序与 [`IrqStats::cpus`] 一致
+#[derive(Debug, Clone)]
+pub struct LineStats {
+    pub intc: DeviceId,
+    pub irq: IrqId,
+    pub per_cpu: Vec<IrqCounter>,
+}
+
+impl LineStats {
+    /// 所有CPU的合计
+    pub fn total(&self) -> IrqCounter {
+        let mut sum = IrqCounter::default();
+        for c in &self.per_cpu {
+            sum.add(c);
+        }
+        sum
+    }
+}
+
+/// [`stats`] 返回的快照，以 `Display` 输出时格式类似 `/proc/interrupts`
+#[derive(Debug, Clone)]
+pub struct IrqStats {
+    pub cpus: Vec<CPUId>,
+    /// 按控制器与中断号排序
+    pub lines: Vec<LineStats>,
+    /// 各CPU的伪中断次数
+    pub spurious: Vec<u64>,
+}
+
+impl IrqStats {
+    pub fn line(&self, intc: DeviceId, irq: IrqId) -> Option<&LineStats> {
+        self.lines.iter().find(|l| l.intc == intc && l.irq == irq)
+    }
+}
+
+impl Display for IrqStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:>12}", "")?;
+        for cpu in &self.cpus {
+            write!(f, " {:>10}", format!("CPU{cpu}"))?;
+        }
+        writeln!(f)?;
+
+        for line in &self.lines {
+            write!(f, "{:>11}:", format!("{:?}", line.irq))?;
+            for c in &line.per_cpu {
+                write!(f, " {:>10}", c.count)?;
+            }
+            let total = line.total();
+            writeln!(
+                f,
+                "  {:?}  avg {:?}  max {:?}",
+                line.intc,
+                total.average(),
+                total.max
+            )?;
+        }
+
+        write!(f, "{:>11}:", "SPU")?;
+        for n in &self.spurious {
+            write!(f, " {n:>10}")?;
+        }
+        writeln!(f)
+    }
+}
+
+/// 记录当前CPU上一次中断的处理耗时
+pub(super) fn record(intc: DeviceId, irq: IrqId, elapsed: Duration) {
+    let Some(cpu) = cpu_global_meybeuninit() else {
+        return;
+    };
+    let _g = NoIrqGuard::new();
+    let mut lines = cpu.irq_stats.lines.lock();
+    let c = lines.entry((intc, irq)).or_default();
+    c.count += 1;
+    c.total += elapsed;
+    c.max = c.max.max(elapsed);
+}
+
+pub(super) fn record_spurious() {
+    if let Some(cpu) = cpu_global_meybeuninit() {
+        cpu.irq_stats.spurious.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// 在线CPU的中断统计快照
+pub fn stats() -> IrqStats {
+    let cpus = platform::cpu_online_list();
+    let mut lines: BTreeMap<(DeviceId, IrqId), Vec<IrqCounter>> = BTreeMap::new();
+    let mut spurious = Vec::with_capacity(cpus.len());
+
+    for (i, &cpu) in cpus.iter().enumerate() {
+        let Some(g) = cpu_global_by_id(cpu) else {
+            spurious.push(0);
+            continue;
+        };
+        spurious.push(g.irq_stats.spurious.load(Ordering::Relaxed));
+
+        let snapshot = {
+            let _g = NoIrqGuard::new();
+            g.irq_stats.lines.lock().clone()
+        };
+        for (key, c) in snapshot {
+            lines
+                .entry(key)
+                .or_insert_with(|| vec![IrqCounter::default(); cpus.len()])[i] = c;
+        }
+    }
+
+    IrqStats {
+        lines: lines
+            .into_iter()
+            .map(|((intc, irq), per_cpu)| LineStats { intc, irq, per_cpu })
+            .collect(),
+        cpus,
+        spurious,
+    }
+}
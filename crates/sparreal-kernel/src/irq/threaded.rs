@@ -0,0 +1,100 @@
+//! 线程化中断：处理函数在中断上下文中只做应答，其余工作在专用的内核任务中执行。
+//!
+//! 唤醒任务时屏蔽中断线，任务执行完毕后重新使能，电平触发的中断源因此不会反复触发。
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use alloc::{boxed::Box, collections::btree_map::BTreeMap, format, sync::Arc};
+use spin::Mutex;
+
+use super::{IrqParam, NoIrqGuard, cascade};
+use crate::{
+    platform,
+    sync::Semaphore,
+    task::{self, PRIORITY_LEVELS, TaskConfig, TaskError},
+};
+
+/// 以处理函数的标识索引
+static THREADS: Mutex<BTreeMap<usize, Arc<IrqThread>>> = Mutex::new(BTreeMap::new());
+
+pub(super) struct IrqThread {
+    param: IrqParam,
+    wake: Semaphore,
+    /// 中断线因等待任务执行而被屏蔽
+    masked: AtomicBool,
+    stop: AtomicBool,
+}
+
+impl IrqThread {
+    /// 在中断上下文中调用
+    pub(super) fn wake(&self) {
+        if !self.masked.swap(true, Ordering::AcqRel) {
+            set_line(&self.param, false);
+        }
+        self.wake.release();
+    }
+}
+
+fn set_line(param: &IrqParam, enable: bool) {
+    match (cascade::get(param.intc), enable) {
+        (Some(cascade), enable) => cascade.set_enable(param.cfg.irq, enable),
+        (None, true) => platform::irq_enable(param.clone()),
+        (None, false) => platform::irq_disable(param.intc, param.cfg.irq),
+    }
+}
+
+/// 创建执行 `thread_fn` 的任务，`handler_id` 为对应处理函数的标识
+pub(super) fn spawn(
+    param: &IrqParam,
+    handler_id: usize,
+    thread_fn: Box<dyn Fn(rdrive::IrqId) + Send>,
+) -> Result<Arc<IrqThread>, TaskError> {
+    let thread = Arc::new(IrqThread {
+        param: param.clone(),
+        wake: Semaphore::new(0),
+        masked: AtomicBool::new(false),
+        stop: AtomicBool::new(false),
+    });
+
+    let irq = param.cfg.irq;
+    let config = TaskConfig {
+        priority: PRIORITY_LEVELS - 1,
+        stack_size: platform::kstack_size(),
+        ..TaskConfig::new(format!("irq/{irq:?}"))
+    };
+    let t = thread.clone();
+    task::spawn_with_config(
+        move || loop {
+            t.wake.acquire();
+            if t.stop.load(Ordering::Acquire) {
+                break;
+            }
+            thread_fn(irq);
+            if t.masked.swap(false, Ordering::AcqRel) && !t.stop.load(Ordering::Acquire) {
+                set_line(&t.param, true);
+            }
+        },
+        config,
+    )?;
+
+    let _g = NoIrqGuard::new();
+    THREADS.lock().insert(handler_id, thread.clone());
+    Ok(thread)
+}
+
+/// 处理函数注销后结束其任务，`line_in_use` 表示中断线上仍有其他处理函数
+pub(super) fn stop(handler_id: usize, line_in_use: bool) {
+    let thread = {
+        let _g = NoIrqGuard::new();
+        THREADS.lock().remove(&handler_id)
+    };
+    let Some(thread) = thread else {
+        return;
+    };
+    thread.stop.store(true, Ordering::Release);
+    thread.wake.release();
+    // 任务不会再使能中断线，其他处理函数仍需要它
+    if thread.masked.swap(false, Ordering::AcqRel) && line_in_use {
+        set_line(&thread.param, true);
+    }
+}
@@ -4,3 +4,9 @@ fn panic(info: &core::panic::PanicInfo) -> ! {
     log::error!("kernel panic: {info:?}");
     crate::platform::shutdown()
 }
+
+#[cfg(target_os = "none")]
+#[alloc_error_handler]
+fn alloc_error(layout: core::alloc::Layout) -> ! {
+    crate::mem::oom::alloc_error(layout)
+}
@@ -2,6 +2,8 @@
 #![feature(linkage)]
 #![feature(fn_align)]
 #![feature(allocator_api)]
+#![feature(alloc_error_handler)]
+#![cfg_attr(feature = "alloc-track", feature(btreemap_alloc))]
 
 extern crate alloc;
 #[macro_use]
@@ -26,6 +28,8 @@ mod lang_items;
 pub mod mem;
 pub mod platform;
 pub mod prelude;
+pub mod sync;
+pub mod syscall;
 pub mod task;
 pub mod time;
 
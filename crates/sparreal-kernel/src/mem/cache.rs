@@ -1,21 +1,49 @@
-use core::ptr::NonNull;
+use core::{alloc::Layout, ptr::NonNull};
 
-use dma_api::Osal;
+use dma_api::{Direction, Osal};
 
 use crate::platform::{self, CacheOp};
 
-use super::{PhysAddr, VirtAddr};
+use super::{PhysAddr, VirtAddr, dma, frame::FRAMES, frame::phys_to_virt};
 
 struct DMAImpl;
 
+/// dma-api 的映射记录在 [`dma`] 中，`flush` 与 `invalidate` 按记录同步，包括回弹缓冲区
 impl Osal for DMAImpl {
-    fn map(&self, addr: NonNull<u8>, _size: usize, _direction: dma_api::Direction) -> u64 {
-        let vaddr = VirtAddr::from(addr);
-        let paddr = PhysAddr::from(vaddr);
-        paddr.raw() as _
+    fn map(&self, addr: NonNull<u8>, size: usize, direction: Direction) -> u64 {
+        dma::map_tracked(addr, size, direction)
     }
 
-    fn unmap(&self, _addr: NonNull<u8>, _size: usize) {}
+    fn unmap(&self, addr: NonNull<u8>, size: usize) {
+        dma::unmap_tracked(addr, size);
+    }
+
+    fn flush(&self, addr: NonNull<u8>, size: usize) {
+        let addr = addr.as_ptr() as usize;
+        if !dma::with_tracked(addr, size, |m, offset| m.sync_for_device(offset, size)) {
+            platform::dcache_range(CacheOp::Clean, addr, size);
+        }
+    }
+
+    fn invalidate(&self, addr: NonNull<u8>, size: usize) {
+        let addr = addr.as_ptr() as usize;
+        if !dma::with_tracked(addr, size, |m, offset| m.sync_for_cpu(offset, size)) {
+            platform::dcache_range(CacheOp::Invalidate, addr, size);
+        }
+    }
+
+    /// 直接从页帧分配，物理地址不超过 `dma_mask`
+    unsafe fn alloc(&self, dma_mask: u64, layout: Layout) -> *mut u8 {
+        match dma::alloc_frames(dma_mask, layout) {
+            Some(addr) => phys_to_virt(addr.raw()) as *mut u8,
+            None => core::ptr::null_mut(),
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let paddr = PhysAddr::from(VirtAddr::from(ptr as usize));
+        FRAMES.dealloc(paddr, layout);
+    }
 }
 
 pub fn init() {
@@ -0,0 +1,409 @@
+//! 流式 DMA 映射与一致性 DMA 内存。
+//!
+//! 流式映射按传输方向维护缓存：交给设备前写回，设备写入后 CPU 读取前无效化。
+//! 缓冲区超出设备的寻址范围或物理不连续时，改用满足要求的回弹缓冲区，同步时复制数据。
+//!
+//! 一致性内存以不可缓存属性映射到专用的虚拟地址区域，CPU 与设备之间无需同步。
+
+use core::{
+    alloc::Layout,
+    ops::Range,
+    ptr::NonNull,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use alloc::{collections::btree_map::BTreeMap, sync::Arc};
+use page_table_generic::PagingError;
+use rdrive::DeviceId;
+use spin::Mutex;
+
+pub use dma_api::Direction;
+
+use super::{
+    PhysAddr, VirtAddr, align_up,
+    frame::{DMA32_END, FRAMES, Zone, phys_to_virt},
+    iommu::{self, IommuDomain, IommuError},
+    mmu::{self, AccessSetting, CacheSetting, va::VaAllocator},
+    page_size,
+};
+use crate::{
+    hal_al::mmu::MapConfig,
+    irq::NoIrqGuard,
+    platform::{self, CacheOp},
+};
+
+/// 一致性 DMA 内存所用的虚拟地址区域
+pub const COHERENT_REGION: Range<usize> = 0xffff_2000_0000_0000..0xffff_2100_0000_0000;
+
+static COHERENT_AREA: Mutex<VaAllocator> = Mutex::new(VaAllocator::new(COHERENT_REGION));
+
+#[derive(Debug)]
+pub enum DmaError {
+    NoMemory,
+    NoVirtualSpace,
+    Paging(PagingError),
+    Iommu(IommuError),
+}
+
+/// 分配 `[pa, pa + layout.size())` 不超过 `dma_mask` 的物理页，
+/// `dma_mask` 不超过 32 位时只使用 [`Zone::Dma32`]
+pub(crate) fn alloc_frames(dma_mask: u64, layout: Layout) -> Option<PhysAddr> {
+    let zone = if dma_mask < DMA32_END as u64 {
+        Zone::Dma32
+    } else {
+        Zone::High
+    };
+    let addr = FRAMES.alloc(layout, zone)?;
+    if !fits(addr, layout.size(), dma_mask) {
+        FRAMES.dealloc(addr, layout);
+        return None;
+    }
+    Some(addr)
+}
+
+fn fits(addr: PhysAddr, size: usize, dma_mask: u64) -> bool {
+    (addr.raw() + size.max(1) - 1) as u64 <= dma_mask
+}
+
+fn dcache(op: CacheOp, addr: usize, size: usize) {
+    if size > 0 {
+        platform::dcache_range(op, addr, size);
+    }
+}
+
+/// 设备访问前的缓存维护
+fn cache_for_device(addr: usize, size: usize, direction: Direction) {
+    match direction {
+        Direction::ToDevice => dcache(CacheOp::Clean, addr, size),
+        // 同时写回，避免缓冲区两端与其他数据共享的脏缓存行之后被逐出覆盖设备写入的数据
+        Direction::FromDevice | Direction::Bidirectional => {
+            dcache(CacheOp::CleanAndInvalidate, addr, size)
+        }
+    }
+}
+
+/// 设备写入后 CPU 读取前的缓存维护
+fn cache_for_cpu(addr: usize, size: usize, direction: Direction) {
+    if direction != Direction::ToDevice {
+        dcache(CacheOp::Invalidate, addr, size);
+    }
+}
+
+/// `[va, va + size)` 物理连续时返回起始物理地址
+fn phys_contiguous(va: usize, size: usize) -> Option<PhysAddr> {
+    let (start, _) = mmu::translate(VirtAddr::from(va))?;
+    let first_page = align_up(va + 1, page_size());
+    for page in (first_page..va + size).step_by(page_size()) {
+        let (pa, _) = mmu::translate(VirtAddr::from(page))?;
+        if pa.raw() != start.raw() + (page - va) {
+            return None;
+        }
+    }
+    Some(start)
+}
+
+struct Bounce {
+    paddr: PhysAddr,
+    layout: Layout,
+}
+
+impl Bounce {
+    fn addr(&self) -> usize {
+        phys_to_virt(self.paddr.raw())
+    }
+}
+
+/// 一个流式 DMA 映射，丢弃时完成 CPU 侧同步并解除映射
+pub struct DmaMapping {
+    vaddr: NonNull<u8>,
+    size: usize,
+    direction: Direction,
+    bus_addr: u64,
+    bounce: Option<Bounce>,
+    /// 经 IOMMU 映射时 `bus_addr` 是其中的 I/O 地址
+    domain: Option<Arc<IommuDomain>>,
+}
+
+unsafe impl Send for DmaMapping {}
+unsafe impl Sync for DmaMapping {}
+
+/// 将 `[ptr, ptr + size)` 交给寻址范围为 `dma_mask` 的设备，映射期间 CPU 不应访问。
+///
+/// 映射后已完成设备侧同步。
+pub fn map_single(
+    ptr: NonNull<u8>,
+    size: usize,
+    direction: Direction,
+    dma_mask: u64,
+) -> Result<DmaMapping, DmaError> {
+    let va = ptr.as_ptr() as usize;
+    if let Some(pa) = phys_contiguous(va, size).filter(|&pa| fits(pa, size, dma_mask)) {
+        cache_for_device(va, size, direction);
+        return Ok(DmaMapping {
+            vaddr: ptr,
+            size,
+            direction,
+            bus_addr: pa.raw() as u64,
+            bounce: None,
+            domain: None,
+        });
+    }
+
+    let layout = Layout::from_size_align(align_up(size.max(1), page_size()), page_size())
+        .map_err(|_| DmaError::NoMemory)?;
+    let paddr = alloc_frames(dma_mask, layout).ok_or(DmaError::NoMemory)?;
+    let bounce = Bounce { paddr, layout };
+    if direction != Direction::FromDevice {
+        unsafe { core::ptr::copy_nonoverlapping(ptr.as_ptr(), bounce.addr() as *mut u8, size) };
+    }
+    cache_for_device(bounce.addr(), size, direction);
+    Ok(DmaMapping {
+        vaddr: ptr,
+        size,
+        direction,
+        bus_addr: paddr.raw() as u64,
+        bounce: Some(bounce),
+        domain: None,
+    })
+}
+
+/// 将 `[ptr, ptr + size)` 映射到设备的 IOMMU 地址空间 `domain`，不需要回弹缓冲区
+pub fn map_single_iommu(
+    domain: &Arc<IommuDomain>,
+    ptr: NonNull<u8>,
+    size: usize,
+    direction: Direction,
+) -> Result<DmaMapping, DmaError> {
+    let va = ptr.as_ptr() as usize;
+    let iova = domain.map(va, size).map_err(DmaError::Iommu)?;
+    cache_for_device(va, size, direction);
+    Ok(DmaMapping {
+        vaddr: ptr,
+        size,
+        direction,
+        bus_addr: iova,
+        bounce: None,
+        domain: Some(domain.clone()),
+    })
+}
+
+/// 为设备 `dev` 映射 `[ptr, ptr + size)`：设备登记了 IOMMU 地址空间时经其翻译，否则同 [`map_single`]
+pub fn map_device(
+    dev: DeviceId,
+    ptr: NonNull<u8>,
+    size: usize,
+    direction: Direction,
+    dma_mask: u64,
+) -> Result<DmaMapping, DmaError> {
+    match iommu::device_domain(dev) {
+        Some(domain) => map_single_iommu(&domain, ptr, size, direction),
+        None => map_single(ptr, size, direction, dma_mask),
+    }
+}
+
+impl DmaMapping {
+    pub fn bus_addr(&self) -> u64 {
+        self.bus_addr
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn direction(&self) -> Direction {
+        self.direction
+    }
+
+    pub fn is_bounced(&self) -> bool {
+        self.bounce.is_some()
+    }
+
+    /// 映射是否包含 `[addr, addr + size)`
+    fn contains(&self, addr: usize, size: usize) -> bool {
+        let start = self.vaddr.as_ptr() as usize;
+        start <= addr && addr + size <= start + self.size
+    }
+
+    fn range(&self, offset: usize, size: usize) -> (usize, usize) {
+        assert!(
+            offset + size <= self.size,
+            "dma sync [{offset:#x}, +{size:#x}) out of mapping size {:#x}",
+            self.size
+        );
+        let addr = self.vaddr.as_ptr() as usize + offset;
+        let target = match &self.bounce {
+            Some(b) => b.addr() + offset,
+            None => addr,
+        };
+        (addr, target)
+    }
+
+    /// CPU 修改 `[offset, offset + size)` 后交还设备
+    pub fn sync_for_device(&self, offset: usize, size: usize) {
+        let (addr, target) = self.range(offset, size);
+        if self.bounce.is_some() && self.direction != Direction::FromDevice {
+            unsafe { core::ptr::copy_nonoverlapping(addr as *const u8, target as *mut u8, size) };
+        }
+        cache_for_device(target, size, self.direction);
+    }
+
+    /// CPU 读取设备写入的 `[offset, offset + size)` 前调用
+    pub fn sync_for_cpu(&self, offset: usize, size: usize) {
+        let (addr, target) = self.range(offset, size);
+        cache_for_cpu(target, size, self.direction);
+        if self.bounce.is_some() && self.direction != Direction::ToDevice {
+            unsafe { core::ptr::copy_nonoverlapping(target as *const u8, addr as *mut u8, size) };
+        }
+    }
+}
+
+impl Drop for DmaMapping {
+    fn drop(&mut self) {
+        self.sync_for_cpu(0, self.size);
+        if let Some(b) = self.bounce.take() {
+            FRAMES.dealloc(b.paddr, b.layout);
+        }
+        if let Some(domain) = self.domain.take() {
+            domain.unmap(self.bus_addr, self.size);
+        }
+    }
+}
+
+/// dma-api 建立的流式映射，以 (虚拟地址, 序号) 为键，允许同一缓冲区被映射多次
+static STREAMING: Mutex<BTreeMap<(usize, u64), DmaMapping>> = Mutex::new(BTreeMap::new());
+static STREAMING_SEQ: AtomicU64 = AtomicU64::new(0);
+
+/// 建立映射并记录，供 dma-api 之后按地址同步与解除
+pub(crate) fn map_tracked(ptr: NonNull<u8>, size: usize, direction: Direction) -> u64 {
+    // dma-api 不提供设备及其寻址范围，映射后由其自行检查；在 IOMMU 之后的设备使用 [`map_device`]
+    let mapping = map_single(ptr, size, direction, u64::MAX)
+        .unwrap_or_else(|e| panic!("dma map {ptr:p} +{size:#x} failed: {e:?}"));
+    let bus = mapping.bus_addr();
+    let seq = STREAMING_SEQ.fetch_add(1, Ordering::Relaxed);
+    let _g = NoIrqGuard::new();
+    STREAMING
+        .lock()
+        .insert((ptr.as_ptr() as usize, seq), mapping);
+    bus
+}
+
+pub(crate) fn unmap_tracked(ptr: NonNull<u8>, size: usize) {
+    let va = ptr.as_ptr() as usize;
+    let mapping = {
+        let _g = NoIrqGuard::new();
+        let mut streaming = STREAMING.lock();
+        let key = streaming
+            .range((va, 0)..=(va, u64::MAX))
+            .find(|(_, m)| m.size == size)
+            .map(|(k, _)| *k);
+        key.and_then(|k| streaming.remove(&k))
+    };
+    match mapping {
+        Some(m) => drop(m),
+        None => warn!("dma unmap of unknown mapping {va:#x} +{size:#x}"),
+    }
+}
+
+/// 在包含 `[addr, addr + size)` 的映射上执行 `f`，没有时返回 `false`
+pub(crate) fn with_tracked(addr: usize, size: usize, f: impl FnOnce(&DmaMapping, usize)) -> bool {
+    let _g = NoIrqGuard::new();
+    let streaming = STREAMING.lock();
+    match streaming
+        .range(..=(addr, u64::MAX))
+        .rev()
+        .find(|(_, m)| m.contains(addr, size))
+    {
+        Some((&(va, _), m)) => {
+            f(m, addr - va);
+            true
+        }
+        None => false,
+    }
+}
+
+/// 以不可缓存属性映射的 DMA 内存，丢弃时释放
+pub struct DmaCoherent {
+    vaddr: NonNull<u8>,
+    paddr: PhysAddr,
+    layout: Layout,
+}
+
+unsafe impl Send for DmaCoherent {}
+unsafe impl Sync for DmaCoherent {}
+
+/// 从一致性内存池分配至少 `size` 字节，按页取整并清零，物理地址不超过 `dma_mask`
+pub fn alloc_coherent(size: usize, dma_mask: u64) -> Result<DmaCoherent, DmaError> {
+    let size = align_up(size.max(1), page_size());
+    let layout = Layout::from_size_align(size, page_size()).map_err(|_| DmaError::NoMemory)?;
+    let paddr = alloc_frames(dma_mask, layout).ok_or(DmaError::NoMemory)?;
+
+    // 线性映射中可能残留该内存的缓存行，写回并无效化，避免之后被逐出覆盖设备写入的数据
+    let linear = phys_to_virt(paddr.raw());
+    unsafe { (linear as *mut u8).write_bytes(0, size) };
+    dcache(CacheOp::CleanAndInvalidate, linear, size);
+
+    let va = {
+        let _g = NoIrqGuard::new();
+        COHERENT_AREA.lock().alloc(size)
+    };
+    let Some(va) = va else {
+        FRAMES.dealloc(paddr, layout);
+        return Err(DmaError::NoVirtualSpace);
+    };
+    if let Err(e) = mmu::map(&MapConfig {
+        name: "dma-coherent",
+        va_start: va.start.into(),
+        pa_start: paddr,
+        size,
+        access: AccessSetting::ReadWrite,
+        cache: CacheSetting::NonCacheable,
+    }) {
+        let _g = NoIrqGuard::new();
+        COHERENT_AREA.lock().free(va);
+        FRAMES.dealloc(paddr, layout);
+        return Err(DmaError::Paging(e));
+    }
+
+    Ok(DmaCoherent {
+        vaddr: unsafe { NonNull::new_unchecked(va.start as *mut u8) },
+        paddr,
+        layout,
+    })
+}
+
+impl DmaCoherent {
+    pub fn as_ptr(&self) -> NonNull<u8> {
+        self.vaddr
+    }
+
+    pub fn bus_addr(&self) -> u64 {
+        self.paddr.raw() as u64
+    }
+
+    pub fn size(&self) -> usize {
+        self.layout.size()
+    }
+}
+
+impl Drop for DmaCoherent {
+    fn drop(&mut self) {
+        let va = self.vaddr.as_ptr() as usize;
+        let size = self.layout.size();
+        if let Err(e) = mmu::unmap(va.into(), size) {
+            panic!("dma coherent unmap [{va:#x}, +{size:#x}) failed: {e:?}");
+        }
+        let _g = NoIrqGuard::new();
+        COHERENT_AREA.lock().free(va..va + size);
+        FRAMES.dealloc(self.paddr, self.layout);
+    }
+}
+
+impl core::fmt::Debug for DmaCoherent {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("DmaCoherent")
+            .field("vaddr", &self.vaddr)
+            .field("paddr", &self.paddr)
+            .field("size", &self.layout.size())
+            .finish()
+    }
+}
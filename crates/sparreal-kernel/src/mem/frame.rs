@@ -0,0 +1,310 @@
+//! 物理页帧分配器，按页管理启动时报告的全部可用内存。
+//!
+//! 内存按物理地址分为 [`Zone::Dma32`]（4GiB 以下）与 [`Zone::High`] 两个区。
+//! 每段连续内存用一张位图记录页的占用，位图放在主内存开头，分配器本身不使用堆。
+
+use core::{
+    alloc::Layout,
+    ops::Range,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use arrayvec::ArrayVec;
+use spin::Mutex;
+
+use super::{PhysAddr, PhysCRange, align_down, align_up, mmu::LINER_OFFSET, page_size};
+use crate::{irq::NoIrqGuard, println};
+
+/// [`Zone::Dma32`] 的上界
+pub const DMA32_END: usize = 1 << 32;
+
+const MAX_AREAS: usize = 64;
+
+/// 线性映射是否已建立，之前只能通过恒等映射访问主内存
+static LINEAR_MAPPED: AtomicBool = AtomicBool::new(false);
+
+pub static FRAMES: FrameAllocator = FrameAllocator::new();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Zone {
+    /// 4GiB 以下，供只能访问 32 位地址的设备使用
+    Dma32,
+    High,
+}
+
+/// 单个区的页数统计
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ZoneStats {
+    pub total: usize,
+    pub free: usize,
+    /// 最长的连续空闲页数
+    pub largest_free: usize,
+}
+
+/// 一段连续的物理内存
+struct Area {
+    start: usize,
+    pages: usize,
+    free: usize,
+    zone: Zone,
+    /// 位于主内存内，线性映射建立前即可使用
+    early: bool,
+    /// 位图的物理地址
+    bitmap: usize,
+    /// 下次搜索的起始页
+    next: usize,
+}
+
+impl Area {
+    fn words(&self) -> &[u64] {
+        unsafe {
+            core::slice::from_raw_parts(
+                phys_to_virt(self.bitmap) as *const u64,
+                self.pages.div_ceil(64),
+            )
+        }
+    }
+
+    fn words_mut(&mut self) -> &mut [u64] {
+        unsafe {
+            core::slice::from_raw_parts_mut(
+                phys_to_virt(self.bitmap) as *mut u64,
+                self.pages.div_ceil(64),
+            )
+        }
+    }
+
+    fn end(&self) -> usize {
+        self.start + self.pages * page_size()
+    }
+
+    /// 在 `[from, to)` 中查找 `count` 个连续空闲页，首页物理地址按 `align` 对齐
+    fn find(&self, from: usize, to: usize, count: usize, align: usize) -> Option<usize> {
+        let words = self.words();
+        let aligned =
+            |i: usize| (align_up(self.start + i * page_size(), align) - self.start) / page_size();
+
+        let mut i = aligned(from);
+        while i + count <= to {
+            let Some(j) = (i..i + count).find(|&j| words[j / 64] & (1 << (j % 64)) != 0) else {
+                return Some(i);
+            };
+            // 整个字都已占用时跳过
+            let next = if words[j / 64] == u64::MAX {
+                align_up(j + 1, 64)
+            } else {
+                j + 1
+            };
+            i = aligned(next);
+        }
+        None
+    }
+
+    fn largest_free(&self) -> usize {
+        let words = self.words();
+        let (mut largest, mut run) = (0, 0);
+        for i in 0..self.pages {
+            if words[i / 64] & (1 << (i % 64)) == 0 {
+                run += 1;
+                largest = largest.max(run);
+            } else {
+                run = 0;
+            }
+        }
+        largest
+    }
+
+    fn set(&mut self, range: Range<usize>, used: bool) {
+        let words = self.words_mut();
+        for i in range {
+            if used {
+                words[i / 64] |= 1 << (i % 64);
+            } else {
+                words[i / 64] &= !(1 << (i % 64));
+            }
+        }
+    }
+
+    fn alloc(&mut self, count: usize, align: usize) -> Option<usize> {
+        if self.free < count {
+            return None;
+        }
+        let i = self
+            .find(self.next, self.pages, count, align)
+            .or_else(|| self.find(0, self.pages, count, align))?;
+        self.set(i..i + count, true);
+        self.free -= count;
+        self.next = i + count;
+        Some(self.start + i * page_size())
+    }
+
+    fn dealloc(&mut self, addr: usize, count: usize) {
+        let i = (addr - self.start) / page_size();
+        assert!(
+            i + count <= self.pages,
+            "free frames {addr:#x} out of range"
+        );
+        let words = self.words();
+        for j in i..i + count {
+            assert!(
+                words[j / 64] & (1 << (j % 64)) != 0,
+                "double free of frame {:#x}",
+                self.start + j * page_size()
+            );
+        }
+        self.set(i..i + count, false);
+        self.free += count;
+        self.next = self.next.min(i);
+    }
+}
+
+pub struct FrameAllocator {
+    areas: Mutex<ArrayVec<Area, MAX_AREAS>>,
+}
+
+impl FrameAllocator {
+    pub const fn new() -> Self {
+        Self {
+            areas: Mutex::new(ArrayVec::new_const()),
+        }
+    }
+
+    /// 分配 `layout` 大小的连续物理页，大小按页取整，对齐至少为一页。
+    ///
+    /// `zone` 为 [`Zone::High`] 时优先使用高端内存，不足时再从 [`Zone::Dma32`] 分配。
+    pub fn alloc(&self, layout: Layout, zone: Zone) -> Option<PhysAddr> {
+        let count = align_up(layout.size(), page_size()) / page_size();
+        let align = layout.align().max(page_size());
+        let early = !LINEAR_MAPPED.load(Ordering::Acquire);
+
+        let _g = NoIrqGuard::new();
+        let mut areas = self.areas.lock();
+        let order: &[Zone] = match zone {
+            Zone::Dma32 => &[Zone::Dma32],
+            Zone::High => &[Zone::High, Zone::Dma32],
+        };
+        for z in order {
+            for area in areas.iter_mut() {
+                if area.zone != *z || (early && !area.early) {
+                    continue;
+                }
+                if let Some(addr) = area.alloc(count, align) {
+                    return Some(addr.into());
+                }
+            }
+        }
+        None
+    }
+
+    /// 归还由 [`FrameAllocator::alloc`] 以相同 `layout` 分配的页
+    pub fn dealloc(&self, addr: PhysAddr, layout: Layout) {
+        let count = align_up(layout.size(), page_size()) / page_size();
+        let addr = addr.raw();
+
+        let _g = NoIrqGuard::new();
+        let mut areas = self.areas.lock();
+        let area = areas
+            .iter_mut()
+            .find(|a| (a.start..a.end()).contains(&addr))
+            .unwrap_or_else(|| panic!("frame {addr:#x} is not managed"));
+        area.dealloc(addr, count);
+    }
+
+    pub fn stats(&self, zone: Zone) -> ZoneStats {
+        let _g = NoIrqGuard::new();
+        self.areas
+            .lock()
+            .iter()
+            .filter(|a| a.zone == zone)
+            .fold(ZoneStats::default(), |s, a| ZoneStats {
+                total: s.total + a.pages,
+                free: s.free + a.free,
+                largest_free: s.largest_free.max(a.largest_free()),
+            })
+    }
+}
+
+impl Default for FrameAllocator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// 物理地址在内核中的访问地址
+pub(crate) fn phys_to_virt(addr: usize) -> usize {
+    if LINEAR_MAPPED.load(Ordering::Acquire) {
+        addr + LINER_OFFSET
+    } else {
+        addr
+    }
+}
+
+/// 线性映射已建立，此后可分配全部内存
+pub(crate) fn set_linear_mapped() {
+    LINEAR_MAPPED.store(true, Ordering::Release);
+}
+
+/// 用 `ram` 中的空闲内存初始化分配器，位图从 `main` 开头分配。
+///
+/// 调用时只有主内存可以通过恒等映射访问。
+pub(crate) fn init(ram: &[PhysCRange], main: Range<PhysAddr>) {
+    let ps = page_size();
+    let mut pieces = ArrayVec::<(Range<usize>, Zone), MAX_AREAS>::new();
+    for r in ram {
+        let start = align_up(r.start.raw(), ps);
+        let end = align_down(r.end.raw(), ps);
+        let split = end.min(DMA32_END).max(start);
+        for (range, zone) in [(start..split, Zone::Dma32), (split..end, Zone::High)] {
+            if range.start >= range.end {
+                continue;
+            }
+            if pieces.is_full() {
+                println!("too many memory areas, drop {range:#x?}");
+                continue;
+            }
+            pieces.push((range, zone));
+        }
+    }
+
+    let bitmap_size: usize = pieces
+        .iter()
+        .map(|(r, _)| ((r.end - r.start) / ps).div_ceil(64) * 8)
+        .sum();
+    let main = main.start.raw()..main.end.raw();
+    let reserved = main.start..main.start + align_up(bitmap_size, ps);
+    let mut bitmap = main.start;
+
+    let _g = NoIrqGuard::new();
+    let mut areas = FRAMES.areas.lock();
+    for (mut range, zone) in pieces {
+        // 位图所在的页不参与分配
+        if reserved.contains(&range.start) {
+            range.start = reserved.end;
+        }
+        if range.start >= range.end {
+            continue;
+        }
+
+        let pages = (range.end - range.start) / ps;
+        let mut area = Area {
+            start: range.start,
+            pages,
+            free: pages,
+            zone,
+            early: main.contains(&range.start),
+            bitmap,
+            next: 0,
+        };
+        area.words_mut().fill(0);
+        bitmap += pages.div_ceil(64) * 8;
+
+        println!(
+            "frames [{:#x}, {:#x}) {:?}, {} pages",
+            area.start,
+            area.end(),
+            zone,
+            pages
+        );
+        areas.push(area);
+    }
+}
@@ -0,0 +1,270 @@
+//! IOMMU：设备在独立的 I/O 地址空间中访问内存，DMA 只能访问映射进来的页。
+//!
+//! 硬件驱动实现 [`IommuOps`]，以设备树中 IOMMU 节点的 phandle 注册。
+//! I/O 页表与 CPU 页表格式相同，由平台的 [`Mmu`](crate::hal_al::mmu::Mmu) 接口构建。
+
+use core::ops::Range;
+
+use alloc::{
+    collections::{btree_map::BTreeMap, btree_set::BTreeSet},
+    sync::Arc,
+    vec::Vec,
+};
+use fdt_parser::Node;
+use page_table_generic::PagingError;
+use rdrive::DeviceId;
+use spin::Mutex;
+
+use super::{
+    PhysAddr, VirtAddr, align_down, align_up,
+    mmu::{
+        self, AccessSetting, CacheSetting,
+        table::{PageTable, new_table},
+        va::VaAllocator,
+    },
+    page_size,
+};
+use crate::{
+    hal_al::mmu::{MapConfig, PageTableRef},
+    irq::NoIrqGuard,
+};
+
+/// 设备发出的访问所带的标识，由 IOMMU 用来选择地址空间
+pub type StreamId = u32;
+
+/// I/O 地址范围，位于 4GiB 以下，只能寻址 32 位的设备也无需回弹缓冲区
+pub const IOVA_REGION: Range<usize> = 0x1000..1 << 32;
+
+static IOMMUS: Mutex<BTreeMap<u32, Arc<dyn IommuOps>>> = Mutex::new(BTreeMap::new());
+/// 在 IOMMU 之后的设备所用的地址空间
+static DEVICES: Mutex<BTreeMap<DeviceId, Arc<IommuDomain>>> = Mutex::new(BTreeMap::new());
+/// 已使用的 ASID，与 CPU 的 ASID 相互独立
+static ASIDS: Mutex<BTreeSet<u16>> = Mutex::new(BTreeSet::new());
+
+/// IOMMU 硬件驱动的接口
+pub trait IommuOps: Send + Sync {
+    /// 让 `stream` 的访问经 `table` 翻译，TLB 项以 `asid` 标记
+    fn attach(&self, stream: StreamId, table: PageTableRef, asid: u16) -> Result<(), IommuError>;
+    /// 恢复 `stream` 的默认行为，返回时设备已不再使用原页表
+    fn detach(&self, stream: StreamId);
+    /// 无效化以 `asid` 标记的 TLB 项，返回时已完成
+    fn flush_asid(&self, asid: u16);
+}
+
+#[derive(Debug)]
+pub enum IommuError {
+    /// 没有以该 phandle 注册的 IOMMU
+    NotFound(u32),
+    /// stream 超出 IOMMU 支持的范围
+    InvalidStream(StreamId),
+    /// stream 已绑定到其他地址空间
+    Busy(StreamId),
+    NoAsid,
+    NoIoSpace,
+    /// 要映射的内核地址没有映射
+    NotMapped(VirtAddr),
+    Paging(PagingError),
+}
+
+impl From<PagingError> for IommuError {
+    fn from(value: PagingError) -> Self {
+        Self::Paging(value)
+    }
+}
+
+/// 注册以 `phandle` 为标识的 IOMMU
+pub fn register(phandle: u32, ops: Arc<dyn IommuOps>) {
+    let _g = NoIrqGuard::new();
+    IOMMUS.lock().insert(phandle, ops);
+}
+
+/// 设备树节点 `iommus = <&iommu stream>` 指定的 IOMMU 与 stream
+pub fn fdt_stream(node: &Node<'_>) -> Option<(u32, StreamId)> {
+    let mut cells = node.find_property("iommus")?.u32_list();
+    Some((cells.next()?, cells.next()?))
+}
+
+/// PCI 主桥节点 `iommu-map` 中请求者 `rid` 对应的 IOMMU 与 stream
+pub fn fdt_pci_stream(host: &Node<'_>, rid: u32) -> Option<(u32, StreamId)> {
+    let rid = match host.find_property("iommu-map-mask") {
+        Some(mask) => rid & mask.u32(),
+        None => rid,
+    };
+    let cells: Vec<u32> = host.find_property("iommu-map")?.u32_list().collect();
+    let (entries, _) = cells.as_chunks::<4>();
+    entries
+        .iter()
+        .find_map(|&[rid_base, phandle, stream_base, len]| {
+            (rid_base..rid_base + len)
+                .contains(&rid)
+                .then(|| (phandle, stream_base + rid - rid_base))
+        })
+}
+
+/// 登记设备 `dev` 的地址空间，此后 [`dma::map_device`](super::dma::map_device) 为它建立的映射经此翻译
+pub fn attach_device(dev: DeviceId, domain: Arc<IommuDomain>) {
+    let _g = NoIrqGuard::new();
+    DEVICES.lock().insert(dev, domain);
+}
+
+/// 取消登记，返回设备原来的地址空间
+pub fn detach_device(dev: DeviceId) -> Option<Arc<IommuDomain>> {
+    let _g = NoIrqGuard::new();
+    DEVICES.lock().remove(&dev)
+}
+
+/// 设备 `dev` 的地址空间，不在 IOMMU 之后或未登记时为 `None`
+pub fn device_domain(dev: DeviceId) -> Option<Arc<IommuDomain>> {
+    let _g = NoIrqGuard::new();
+    DEVICES.lock().get(&dev).cloned()
+}
+
+/// 驱动探测设备时调用：节点在 IOMMU 之后时按 `iommus` 属性建立地址空间并登记给 `dev`
+pub fn probe_device(
+    dev: DeviceId,
+    node: &Node<'_>,
+) -> Result<Option<Arc<IommuDomain>>, IommuError> {
+    let Some(domain) = IommuDomain::from_fdt(node).transpose()? else {
+        return Ok(None);
+    };
+    attach_device(dev, domain.clone());
+    Ok(Some(domain))
+}
+
+fn asid_alloc() -> Option<u16> {
+    let _g = NoIrqGuard::new();
+    let mut asids = ASIDS.lock();
+    let asid = (1..=u16::MAX).find(|a| !asids.contains(a))?;
+    asids.insert(asid);
+    Some(asid)
+}
+
+fn asid_free(asid: u16) {
+    let _g = NoIrqGuard::new();
+    ASIDS.lock().remove(&asid);
+}
+
+/// 一个设备的 I/O 地址空间，丢弃时解除绑定
+pub struct IommuDomain {
+    iommu: Arc<dyn IommuOps>,
+    stream: StreamId,
+    asid: u16,
+    table: Mutex<PageTable>,
+    iova: Mutex<VaAllocator>,
+}
+
+impl IommuDomain {
+    /// 为 `stream` 建立空的地址空间并交给 phandle 为 `iommu` 的 IOMMU
+    pub fn new(iommu: u32, stream: StreamId) -> Result<Arc<Self>, IommuError> {
+        let ops = {
+            let _g = NoIrqGuard::new();
+            IOMMUS.lock().get(&iommu).cloned()
+        }
+        .ok_or(IommuError::NotFound(iommu))?;
+
+        let table = new_table()?;
+        let asid = asid_alloc().ok_or(IommuError::NoAsid)?;
+        if let Err(e) = ops.attach(stream, table.raw(), asid) {
+            asid_free(asid);
+            return Err(e);
+        }
+        Ok(Arc::new(Self {
+            iommu: ops,
+            stream,
+            asid,
+            table: Mutex::new(table),
+            iova: Mutex::new(VaAllocator::new(IOVA_REGION)),
+        }))
+    }
+
+    /// 按设备树节点的 `iommus` 属性建立，节点不在 IOMMU 之后时为 `None`
+    pub fn from_fdt(node: &Node<'_>) -> Option<Result<Arc<Self>, IommuError>> {
+        let (iommu, stream) = fdt_stream(node)?;
+        Some(Self::new(iommu, stream))
+    }
+
+    pub fn stream(&self) -> StreamId {
+        self.stream
+    }
+
+    /// 将内核地址 `[va, va + size)` 所在的页映射到新分配的 I/O 地址，返回 `va` 对应的 I/O 地址。
+    ///
+    /// 各页物理上不必连续。
+    pub fn map(&self, va: usize, size: usize) -> Result<u64, IommuError> {
+        let start = align_down(va, page_size());
+        let len = align_up(va + size.max(1), page_size()) - start;
+        let iova = {
+            let _g = NoIrqGuard::new();
+            self.iova.lock().alloc(len)
+        }
+        .ok_or(IommuError::NoIoSpace)?;
+
+        let res = (0..len).step_by(page_size()).try_for_each(|offset| {
+            let page = VirtAddr::from(start + offset);
+            let (pa, _) = mmu::translate(page).ok_or(IommuError::NotMapped(page))?;
+            self.map_page(iova.start + offset, pa)
+        });
+        if let Err(e) = res {
+            self.unmap_range(iova);
+            return Err(e);
+        }
+        Ok((iova.start + (va - start)) as u64)
+    }
+
+    fn map_page(&self, iova: usize, pa: PhysAddr) -> Result<(), IommuError> {
+        let _g = NoIrqGuard::new();
+        self.table.lock().map(&MapConfig {
+            name: "iommu",
+            va_start: iova.into(),
+            pa_start: pa,
+            size: page_size(),
+            access: AccessSetting::ReadWrite,
+            cache: CacheSetting::Normal,
+        })?;
+        Ok(())
+    }
+
+    /// 解除 [`map`](Self::map) 建立的映射，`iova` 与 `size` 与映射时一致
+    pub fn unmap(&self, iova: u64, size: usize) {
+        let iova = iova as usize;
+        let start = align_down(iova, page_size());
+        let end = align_up(iova + size.max(1), page_size());
+        self.unmap_range(start..end);
+    }
+
+    fn unmap_range(&self, range: Range<usize>) {
+        let size = range.end - range.start;
+        let _g = NoIrqGuard::new();
+        if let Err(e) = self.table.lock().unmap(range.start.into(), size) {
+            panic!("iommu unmap [{:#x}, +{size:#x}) failed: {e:?}", range.start);
+        }
+        self.iommu.flush_asid(self.asid);
+        self.iova.lock().free(range);
+    }
+
+    /// 查询 I/O 地址 `iova` 映射的物理地址
+    pub fn translate(&self, iova: u64) -> Option<PhysAddr> {
+        let _g = NoIrqGuard::new();
+        self.table
+            .lock()
+            .translate(VirtAddr::from(iova as usize))
+            .map(|(pa, _)| pa)
+    }
+}
+
+impl Drop for IommuDomain {
+    fn drop(&mut self) {
+        self.iommu.detach(self.stream);
+        self.iommu.flush_asid(self.asid);
+        asid_free(self.asid);
+    }
+}
+
+impl core::fmt::Debug for IommuDomain {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("IommuDomain")
+            .field("stream", &self.stream)
+            .field("asid", &self.asid)
+            .finish()
+    }
+}
@@ -0,0 +1,213 @@
+//! 设备内存映射，从专用的虚拟地址区域分配。
+//!
+//! 重叠的物理区间共用同一映射并计数，最后一个引用释放时解除映射。
+//! 同一段物理内存不允许以不同的缓存属性映射。
+
+use core::{mem::ManuallyDrop, ops::Range, ptr::NonNull};
+
+use alloc::vec::Vec;
+use page_table_generic::PagingError;
+use spin::Mutex;
+
+use super::{
+    PhysAddr, align_down, align_up,
+    mmu::{self, AccessSetting, CacheSetting, va::VaAllocator},
+    page_size,
+};
+use crate::{hal_al::mmu::MapConfig, irq::NoIrqGuard};
+
+/// 设备内存所用的虚拟地址区域
+pub const IOREMAP_REGION: Range<usize> = 0xffff_1000_0000_0000..0xffff_2000_0000_0000;
+
+static IOMAPS: Mutex<IoMaps> = Mutex::new(IoMaps {
+    area: VaAllocator::new(IOREMAP_REGION),
+    maps: Vec::new(),
+});
+
+#[derive(Debug)]
+pub enum IoRemapError {
+    /// 与已有映射的缓存属性冲突
+    Conflict {
+        paddr: PhysAddr,
+        existing: CacheSetting,
+    },
+    NoVirtualSpace,
+    Paging(PagingError),
+}
+
+struct IoMaps {
+    area: VaAllocator,
+    maps: Vec<IoMap>,
+}
+
+struct IoMap {
+    /// 按页对齐的物理区间
+    phys: Range<usize>,
+    va: usize,
+    cache: CacheSetting,
+    refs: usize,
+}
+
+/// 将 `[paddr, paddr + size)` 映射为 `cache` 属性的设备内存。
+///
+/// 已有映射完整包含该区间且属性相同时直接复用。
+pub fn ioremap(
+    paddr: PhysAddr,
+    size: usize,
+    cache: CacheSetting,
+) -> Result<MmioRegion, IoRemapError> {
+    let start = align_down(paddr.raw(), page_size());
+    let end = align_up(paddr.raw() + size.max(1), page_size());
+
+    let _g = NoIrqGuard::new();
+    let mut iomaps = IOMAPS.lock();
+
+    if let Some(conflict) = iomaps
+        .maps
+        .iter()
+        .find(|m| m.phys.start < end && start < m.phys.end && m.cache != cache)
+    {
+        return Err(IoRemapError::Conflict {
+            paddr: conflict.phys.start.into(),
+            existing: conflict.cache,
+        });
+    }
+
+    let map = match iomaps
+        .maps
+        .iter_mut()
+        .find(|m| m.phys.start <= start && end <= m.phys.end)
+    {
+        Some(map) => {
+            map.refs += 1;
+            map
+        }
+        None => {
+            let va = iomaps
+                .area
+                .alloc(end - start)
+                .ok_or(IoRemapError::NoVirtualSpace)?;
+            if let Err(e) = mmu::map(&MapConfig {
+                name: "ioremap",
+                va_start: va.start.into(),
+                pa_start: start.into(),
+                size: end - start,
+                access: AccessSetting::ReadWrite,
+                cache,
+            }) {
+                iomaps.area.free(va);
+                return Err(IoRemapError::Paging(e));
+            }
+            iomaps.maps.push(IoMap {
+                phys: start..end,
+                va: va.start,
+                cache,
+                refs: 1,
+            });
+            iomaps.maps.last_mut().unwrap()
+        }
+    };
+
+    let va = map.va + (paddr.raw() - map.phys.start);
+    Ok(MmioRegion {
+        base: unsafe { NonNull::new_unchecked(va as *mut u8) },
+        size,
+        paddr,
+        map_va: map.va,
+    })
+}
+
+/// 释放映射，等同于丢弃 `region`
+pub fn iounmap(region: MmioRegion) {
+    drop(region);
+}
+
+fn release(map_va: usize) {
+    let _g = NoIrqGuard::new();
+    let mut iomaps = IOMAPS.lock();
+    let i = iomaps
+        .maps
+        .iter()
+        .position(|m| m.va == map_va)
+        .unwrap_or_else(|| panic!("iounmap of unknown mapping {map_va:#x}"));
+
+    let map = &mut iomaps.maps[i];
+    map.refs -= 1;
+    if map.refs > 0 {
+        return;
+    }
+
+    let map = iomaps.maps.swap_remove(i);
+    let size = map.phys.end - map.phys.start;
+    if let Err(e) = mmu::unmap(map.va.into(), size) {
+        panic!("iounmap [{:#x}, +{size:#x}) failed: {e:?}", map.va);
+    }
+    iomaps.area.free(map.va..map.va + size);
+}
+
+/// 一段已映射的设备内存，丢弃时释放映射
+pub struct MmioRegion {
+    base: NonNull<u8>,
+    size: usize,
+    paddr: PhysAddr,
+    map_va: usize,
+}
+
+unsafe impl Send for MmioRegion {}
+unsafe impl Sync for MmioRegion {}
+
+impl MmioRegion {
+    pub fn as_ptr(&self) -> NonNull<u8> {
+        self.base
+    }
+
+    pub fn paddr(&self) -> PhysAddr {
+        self.paddr
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// 保留映射直到系统关闭，返回映射地址
+    pub fn leak(self) -> NonNull<u8> {
+        ManuallyDrop::new(self).base
+    }
+
+    fn reg<T>(&self, offset: usize) -> *mut T {
+        assert!(
+            offset + size_of::<T>() <= self.size,
+            "mmio access at {offset:#x} out of range {:#x}",
+            self.size
+        );
+        let ptr = unsafe { self.base.as_ptr().add(offset) } as *mut T;
+        assert!(ptr.is_aligned(), "unaligned mmio access at {offset:#x}");
+        ptr
+    }
+
+    /// 以 volatile 方式读取 `offset` 处的寄存器
+    pub fn read<T: Copy>(&self, offset: usize) -> T {
+        unsafe { self.reg::<T>(offset).read_volatile() }
+    }
+
+    /// 以 volatile 方式写入 `offset` 处的寄存器
+    pub fn write<T: Copy>(&self, offset: usize, value: T) {
+        unsafe { self.reg::<T>(offset).write_volatile(value) }
+    }
+}
+
+impl Drop for MmioRegion {
+    fn drop(&mut self) {
+        release(self.map_va);
+    }
+}
+
+impl core::fmt::Debug for MmioRegion {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("MmioRegion")
+            .field("paddr", &self.paddr)
+            .field("vaddr", &self.base)
+            .field("size", &self.size)
+            .finish()
+    }
+}
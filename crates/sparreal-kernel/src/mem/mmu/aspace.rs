@@ -0,0 +1,244 @@
+use core::{alloc::Layout, fmt::Debug, ops::Range, ptr::NonNull};
+
+use alloc::vec::Vec;
+use page_table_generic::PagingError;
+use spin::Mutex;
+
+use super::table::{PageTable, new_table};
+use crate::{
+    hal_al::mmu::{AccessSetting, CacheSetting, MapConfig, PageTableRef},
+    irq::{NoIrqGuard, ipi},
+    mem::{
+        PhysAddr, Virt, align_up,
+        frame::{FRAMES, Zone, phys_to_virt},
+        page_size,
+    },
+    platform,
+};
+
+/// 用户地址空间上界，低半部分由每个地址空间自己的页表翻译
+pub const USER_SPACE_END: usize = 1 << 48;
+
+/// [`AddressSpace::mmap`] 分配的起始地址
+pub const MMAP_BASE: usize = 0x10_0000_0000;
+
+/// ASID 位宽为 8，0 保留给没有用户页表的任务
+const ASID_COUNT: usize = 256;
+
+static ASIDS: Mutex<[u64; ASID_COUNT / 64]> = Mutex::new([1, 0, 0, 0]);
+
+fn asid_alloc() -> Option<usize> {
+    let _g = NoIrqGuard::new();
+    let mut map = ASIDS.lock();
+    for (i, word) in map.iter_mut().enumerate() {
+        if *word != u64::MAX {
+            let bit = word.trailing_ones() as usize;
+            *word |= 1 << bit;
+            return Some(i * 64 + bit);
+        }
+    }
+    None
+}
+
+fn asid_free(asid: usize) {
+    let _g = NoIrqGuard::new();
+    ASIDS.lock()[asid / 64] &= !(1 << (asid % 64));
+}
+
+/// 独立的用户半部分地址空间，内核半部分在所有地址空间中共享。
+///
+/// 页表以 ASID 标记，切换时无需刷新 TLB。
+pub struct AddressSpace {
+    table: Mutex<PageTable>,
+    raw: PageTableRef,
+    asid: usize,
+    inner: Mutex<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    /// 已映射的用户地址范围
+    mapped: Vec<Range<usize>>,
+    /// 由地址空间分配的内存，释放地址空间时归还
+    frames: Vec<Frame>,
+    mmap_next: usize,
+}
+
+struct Frame {
+    addr: PhysAddr,
+    layout: Layout,
+}
+
+impl AddressSpace {
+    pub fn new() -> Result<Self, PagingError> {
+        let asid = asid_alloc().ok_or(PagingError::NoMemory)?;
+        let mut table = {
+            let _g = NoIrqGuard::new();
+            new_table()
+        }
+        .inspect_err(|_| asid_free(asid))?;
+        table.set_id(asid);
+        Ok(Self {
+            raw: table.raw(),
+            table: Mutex::new(table),
+            asid,
+            inner: Mutex::new(Inner {
+                mmap_next: MMAP_BASE,
+                ..Default::default()
+            }),
+        })
+    }
+
+    pub fn asid(&self) -> usize {
+        self.asid
+    }
+
+    /// # Panics
+    ///
+    /// 映射范围超出 [`USER_SPACE_END`]
+    pub fn map(&self, config: &MapConfig) -> Result<(), PagingError> {
+        assert_user_range(config.va_start, config.size);
+        let _g = NoIrqGuard::new();
+        self.table.lock().map(config)?;
+        let start = config.va_start.raw();
+        self.inner.lock().mapped.push(start..start + config.size);
+        Ok(())
+    }
+
+    /// 分配清零的内存并映射到 `[va_start, va_start + size)`，`size` 按页对齐。
+    ///
+    /// 返回内存在内核中的地址，用于填充内容；内存随地址空间一起释放。
+    ///
+    /// # Panics
+    ///
+    /// 映射范围超出 [`USER_SPACE_END`]
+    pub fn map_alloc(
+        &self,
+        va_start: Virt<u8>,
+        size: usize,
+        access: AccessSetting,
+    ) -> Result<NonNull<u8>, PagingError> {
+        let size = align_up(size, page_size());
+        let layout =
+            Layout::from_size_align(size, page_size()).map_err(|_| PagingError::NoMemory)?;
+        let addr = FRAMES
+            .alloc(layout, Zone::High)
+            .ok_or(PagingError::NoMemory)?;
+        let ptr = unsafe { NonNull::new_unchecked(phys_to_virt(addr.raw()) as *mut u8) };
+        unsafe { ptr.write_bytes(0, size) };
+
+        let res = self.map(&MapConfig {
+            name: "user",
+            va_start,
+            pa_start: addr,
+            size,
+            access,
+            cache: CacheSetting::Normal,
+        });
+        if let Err(e) = res {
+            FRAMES.dealloc(addr, layout);
+            return Err(e);
+        }
+
+        let _g = NoIrqGuard::new();
+        self.inner.lock().frames.push(Frame { addr, layout });
+        Ok(ptr)
+    }
+
+    /// 在 [`MMAP_BASE`] 之上分配一段匿名内存，返回用户地址
+    pub fn mmap(&self, size: usize, access: AccessSetting) -> Result<Virt<u8>, PagingError> {
+        if size > USER_SPACE_END {
+            return Err(PagingError::NoMemory);
+        }
+        let size = align_up(size, page_size());
+        let va = {
+            let _g = NoIrqGuard::new();
+            let mut inner = self.inner.lock();
+            let va = inner.mmap_next;
+            let end = va
+                .checked_add(size)
+                .filter(|&end| end <= USER_SPACE_END)
+                .ok_or(PagingError::NoMemory)?;
+            inner.mmap_next = end;
+            va
+        };
+        self.map_alloc(va.into(), size, access)?;
+        Ok(va.into())
+    }
+
+    /// # Panics
+    ///
+    /// 范围超出 [`USER_SPACE_END`]
+    pub fn unmap(&self, va_start: Virt<u8>, size: usize) -> Result<(), PagingError> {
+        assert_user_range(va_start, size);
+        let _g = NoIrqGuard::new();
+        self.table.lock().unmap(va_start, size)?;
+
+        let (start, end) = (va_start.raw(), va_start.raw() + size);
+        let mut inner = self.inner.lock();
+        let mut rest = Vec::with_capacity(inner.mapped.len());
+        for r in inner.mapped.drain(..) {
+            if r.start < start {
+                rest.push(r.start..r.end.min(start));
+            }
+            if r.end > end {
+                rest.push(r.start.max(end)..r.end);
+            }
+        }
+        inner.mapped = rest;
+        Ok(())
+    }
+
+    /// `[start, start + size)` 是否全部已映射
+    pub fn is_mapped(&self, start: usize, size: usize) -> bool {
+        let Some(end) = start.checked_add(size) else {
+            return false;
+        };
+        let _g = NoIrqGuard::new();
+        let inner = self.inner.lock();
+        let mut cursor = start;
+        while cursor < end {
+            match inner.mapped.iter().find(|r| r.contains(&cursor)) {
+                Some(r) => cursor = r.end,
+                None => return false,
+            }
+        }
+        true
+    }
+}
+
+fn assert_user_range(va_start: Virt<u8>, size: usize) {
+    let start = va_start.raw();
+    assert!(
+        start
+            .checked_add(size)
+            .is_some_and(|end| end <= USER_SPACE_END),
+        "[{start:#x}, +{size:#x}) is out of user space"
+    );
+}
+
+impl Debug for AddressSpace {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("AddressSpace")
+            .field("asid", &self.asid)
+            .field("table", &self.raw.addr)
+            .finish()
+    }
+}
+
+impl Drop for AddressSpace {
+    fn drop(&mut self) {
+        platform::mmu::flush_tlb_asid(self.asid);
+        // 在调度器中回收时屏蔽中断，无法等待其他CPU
+        ipi::tlb_shootdown(platform::irq_all_is_enabled());
+        asid_free(self.asid);
+        for frame in self.inner.get_mut().frames.drain(..) {
+            FRAMES.dealloc(frame.addr, frame.layout);
+        }
+    }
+}
+
+/// 在当前CPU上切换到 `aspace`，`None` 表示只使用内核地址空间
+pub(crate) fn activate(aspace: Option<&AddressSpace>) {
+    platform::mmu::set_user_table(aspace.map(|a| a.raw));
+}
@@ -8,25 +8,24 @@ use core::{
 
 use super::{Phys, PhysAddr, PhysCRange, Virt, once::OnceStatic, region::boot_regions};
 pub use arrayvec::ArrayVec;
-use buddy_system_allocator::Heap;
 use memory_addr::MemoryAddr;
 use page_table_generic::{Access, PagingError};
-use spin::MutexGuard;
 
-pub use crate::hal_al::mmu::{AccessSetting, CacheSetting};
+pub use crate::hal_al::mmu::{AccessSetting, CacheSetting, PageFlags};
 use crate::{
     globals::{self, cpu_inited, global_val},
     hal_al::mmu::{MapConfig, PageTableRef},
     io::print::*,
-    mem::TMP_PAGE_ALLOC_ADDR,
+    mem::frame::{FRAMES, Zone, phys_to_virt},
     platform::{self, mmu::page_size},
     println,
 };
 
-#[cfg(target_os = "none")]
-use crate::mem::ALLOCATOR;
-
+pub mod aspace;
 pub mod table;
+pub(crate) mod va;
+
+pub use aspace::{AddressSpace, MMAP_BASE, USER_SPACE_END};
 
 // pub use paging::init_table;
 // pub use paging::iomap;
@@ -39,53 +38,14 @@ pub fn get_text_va_offset() -> usize {
     *TEXT_OFFSET.get_ref()
 }
 
-pub(crate) fn init_with_tmp_table() {
-    println!("init tmp page table...");
-    let table = new_boot_table().unwrap();
-    platform::mmu::set_kernel_table(table);
-}
-
 pub(crate) fn init() {
     println!("init page table...");
 
+    // 此时页表所用的页帧只能通过恒等映射访问，切换后改用线性映射
     let table_ref = new_table().unwrap();
     let tb = unsafe { table::PageTable::raw_to_own(table_ref) };
     table::set_kernal_table(tb);
-
-    unsafe {
-        let start = TMP_PAGE_ALLOC_ADDR;
-        let end = global_val().main_memory.end.raw();
-        let len = end - start;
-        let start = (start + LINER_OFFSET) as *mut u8;
-        let ram = core::slice::from_raw_parts_mut(start, len);
-
-        ALLOCATOR.add_to_heap(ram);
-        println!(
-            "expand heap [{:#x}, {:#x})",
-            start as usize,
-            start as usize + len
-        );
-    }
-}
-
-struct PageHeap(Heap<32>);
-
-impl page_table_generic::Access for PageHeap {
-    unsafe fn alloc(&mut self, layout: Layout) -> Option<page_table_generic::PhysAddr> {
-        self.0
-            .alloc(layout)
-            .ok()
-            .map(|ptr| (ptr.as_ptr() as usize).into())
-    }
-
-    unsafe fn dealloc(&mut self, ptr: page_table_generic::PhysAddr, layout: Layout) {
-        self.0
-            .dealloc(unsafe { NonNull::new_unchecked(ptr.raw() as _) }, layout);
-    }
-
-    fn phys_to_mut(&self, phys: page_table_generic::PhysAddr) -> *mut u8 {
-        phys.raw() as *mut u8
-    }
+    super::frame::set_linear_mapped();
 }
 
 #[repr(C)]
@@ -133,23 +93,17 @@ impl BootRegion {
         unsafe { CStr::from_ptr(self.name as _).to_str().unwrap() }
     }
 
-    // pub fn va_offset(&self) -> usize {
-    //     match self.kind {
-    //         RegionKind::Stack => {
-    //             if cpu_inited() {
-    //                 self.kind.va_offset()
-    //             } else {
-    //                 // cpu0
-    //                 STACK_BOTTOM - self.range.start.raw()
-    //             }
-    //         }
-    //         _ => self.kind.va_offset(),
-    //     }
-    // }
+    /// 启动时映射所用的虚拟地址偏移
+    pub fn va_offset(&self) -> usize {
+        match self.kind {
+            BootMemoryKind::KImage => platform::mmu::kimage_va_offset(),
+            _ => LINER_OFFSET,
+        }
+    }
 }
 
 #[repr(u8)]
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum BootMemoryKind {
     /// map offset as kv_offset
     KImage,
@@ -168,27 +122,9 @@ impl<T> From<Virt<T>> for Phys<T> {
         }
     }
 }
-const MB: usize = 1024 * 1024;
-
-fn new_boot_table() -> Result<PageTableRef, &'static str> {
-    let mut access = PageHeap(Heap::empty());
-    let main_mem = global_val().main_memory.clone();
-
-    let tmp_end = main_mem.end;
-    let tmp_size = tmp_end - main_mem.start.align_up(MB);
-    let tmp_pt = (main_mem.end - tmp_size / 2).raw();
-
-    unsafe { super::TMP_PAGE_ALLOC_ADDR = tmp_pt };
-
-    println!("page table allocator {:#x}, {:#x}", tmp_pt, tmp_end.raw());
-    unsafe { access.0.add_to_heap(tmp_pt, tmp_end.raw()) };
-    new_table_with_access(&mut access)
-}
 
 fn new_table() -> Result<PageTableRef, &'static str> {
-    let mut g = ALLOCATOR.inner.lock();
-    let mut access = HeapGuard(g);
-    new_table_with_access(&mut access)
+    new_table_with_access(&mut FrameAccess)
 }
 
 fn new_table_with_access(access: &mut dyn Access) -> Result<PageTableRef, &'static str> {
@@ -197,10 +133,7 @@ fn new_table_with_access(access: &mut dyn Access) -> Result<PageTableRef, &'stat
     println!("map boot regions...");
 
     for region in platform::boot_regions() {
-        let offset = match region.kind {
-            BootMemoryKind::KImage => platform::mmu::kimage_va_offset(),
-            _ => LINER_OFFSET,
-        };
+        let offset = region.va_offset();
 
         let pa_start = region.range.start.align_down(page_size());
         let va_start: Virt<u8> = (pa_start + offset).raw().into();
@@ -218,19 +151,35 @@ fn new_table_with_access(access: &mut dyn Access) -> Result<PageTableRef, &'stat
             region.cache
         );
 
-        if let Err(e) = platform::mmu::table_map(
-            table,
-            access,
-            &MapConfig {
-                name: region.name(),
-                va_start,
-                pa_start,
-                size,
-                access: region.access,
-                cache: region.cache,
-            },
-        ) {
-            println!("map error: {e:?}");
+        // 主CPU启动栈的最低一页不映射，作为保护页
+        let guard = region
+            .name()
+            .contains("stack0")
+            .then(|| region.range.start.align_up(page_size()))
+            .filter(|g| *g + page_size() <= pa_end);
+        let parts = match guard {
+            Some(g) => {
+                crate::mem::stack::set_boot_guard((g + offset).raw());
+                [pa_start..g, g + page_size()..pa_end]
+            }
+            None => [pa_start..pa_end, pa_end..pa_end],
+        };
+
+        for part in parts.into_iter().filter(|p| p.start < p.end) {
+            if let Err(e) = platform::mmu::table_map(
+                table,
+                access,
+                &MapConfig {
+                    name: region.name(),
+                    va_start: (part.start + offset).raw().into(),
+                    pa_start: part.start,
+                    size: part.end - part.start,
+                    access: region.access,
+                    cache: region.cache,
+                },
+            ) {
+                println!("map error: {e:?}");
+            }
         }
     }
 
@@ -239,28 +188,39 @@ fn new_table_with_access(access: &mut dyn Access) -> Result<PageTableRef, &'stat
     Ok(table)
 }
 
-struct HeapGuard<'a>(MutexGuard<'a, Heap<32>>);
+/// 页表直接从页帧分配器分配
+pub(crate) struct FrameAccess;
 
-impl Access for HeapGuard<'_> {
+impl Access for FrameAccess {
     unsafe fn alloc(&mut self, layout: Layout) -> Option<page_table_generic::PhysAddr> {
-        self.0
-            .alloc(layout)
-            .ok()
-            .map(|ptr| (ptr.as_ptr() as usize - LINER_OFFSET).into())
+        FRAMES.alloc(layout, Zone::High).map(|pa| pa.raw().into())
     }
 
     unsafe fn dealloc(&mut self, ptr: page_table_generic::PhysAddr, layout: Layout) {
-        self.0.dealloc(
-            unsafe { NonNull::new_unchecked((ptr.raw() + LINER_OFFSET) as _) },
-            layout,
-        );
+        FRAMES.dealloc(ptr.raw().into(), layout);
     }
 
     fn phys_to_mut(&self, phys: page_table_generic::PhysAddr) -> *mut u8 {
-        (phys.raw() + LINER_OFFSET) as *mut u8
+        phys_to_virt(phys.raw()) as *mut u8
     }
 }
 
 pub(crate) fn map(config: &MapConfig) -> Result<(), PagingError> {
     table::with_kernel_table(|t| t.map(config))
 }
+
+pub(crate) fn unmap(va_start: Virt<u8>, size: usize) -> Result<(), PagingError> {
+    table::with_kernel_table(|t| t.unmap(va_start, size))
+}
+
+pub(crate) fn protect(
+    va_start: Virt<u8>,
+    size: usize,
+    access: AccessSetting,
+) -> Result<(), PagingError> {
+    table::with_kernel_table(|t| t.protect(va_start, size, access))
+}
+
+pub(crate) fn translate(va: Virt<u8>) -> Option<(PhysAddr, PageFlags)> {
+    table::with_kernel_table(|t| t.translate(va))
+}
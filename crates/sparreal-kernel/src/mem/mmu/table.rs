@@ -1,14 +1,12 @@
 use page_table_generic::PagingError;
 use spin::Mutex;
 
-#[cfg(target_os = "none")]
-use crate::mem::ALLOCATOR;
 use crate::{
-    hal_al::mmu::MapConfig,
+    hal_al::mmu::{MapConfig, PageFlags},
     irq::NoIrqGuard,
     mem::{
-        Phys, PhysAddr, VirtAddr,
-        mmu::{AccessSetting, CacheSetting, HeapGuard},
+        Phys, PhysAddr, Virt, VirtAddr,
+        mmu::{AccessSetting, CacheSetting, FrameAccess},
     },
     platform,
 };
@@ -25,6 +23,17 @@ pub(crate) fn set_kernal_table(table: PageTable) {
     drop(g);
 }
 
+/// Install the kernel table on the current CPU, used by secondary CPUs that
+/// booted with the boot table.
+pub(crate) fn install_kernel_table() {
+    let g = NoIrqGuard::new();
+    let guard = KERNEL_TABLE.lock();
+    let table = guard.as_ref().expect("kernel table is not initialized");
+    platform::mmu::set_kernel_table(table.raw);
+    drop(guard);
+    drop(g);
+}
+
 pub fn replace_kernel_table(new: PageTable) -> Option<PageTable> {
     let g = NoIrqGuard::new();
     let mut guard = KERNEL_TABLE.lock();
@@ -39,9 +48,7 @@ pub fn replace_kernel_table(new: PageTable) -> Option<PageTable> {
 }
 
 pub fn new_table() -> Result<PageTable, PagingError> {
-    let mut g = ALLOCATOR.inner.lock();
-    let mut access = HeapGuard(g);
-    let raw = platform::mmu::new_table(&mut access)?;
+    let raw = platform::mmu::new_table(&mut FrameAccess)?;
     Ok(unsafe { PageTable::raw_to_own(raw) })
 }
 
@@ -77,17 +84,38 @@ impl PageTable {
         self.raw.addr
     }
 
+    pub(crate) fn raw(&self) -> crate::hal_al::mmu::PageTableRef {
+        self.raw
+    }
+
+    pub(crate) fn set_id(&mut self, id: usize) {
+        self.raw.id = id;
+    }
+
     pub fn map(&mut self, config: &MapConfig) -> Result<(), PagingError> {
-        let mut g = ALLOCATOR.inner.lock();
-        let mut access = HeapGuard(g);
-        platform::mmu::table_map(self.raw, &mut access, config)
+        platform::mmu::table_map(self.raw, &mut FrameAccess, config)
+    }
+
+    pub fn unmap(&mut self, va_start: Virt<u8>, size: usize) -> Result<(), PagingError> {
+        platform::mmu::table_unmap(self.raw, &mut FrameAccess, va_start, size)
+    }
+
+    pub fn protect(
+        &mut self,
+        va_start: Virt<u8>,
+        size: usize,
+        access: AccessSetting,
+    ) -> Result<(), PagingError> {
+        platform::mmu::table_protect(self.raw, &mut FrameAccess, va_start, size, access)
+    }
+
+    pub fn translate(&self, va: Virt<u8>) -> Option<(PhysAddr, PageFlags)> {
+        platform::mmu::table_translate(self.raw, &mut FrameAccess, va)
     }
 }
 
 impl Drop for PageTable {
     fn drop(&mut self) {
-        let mut g = ALLOCATOR.inner.lock();
-        let mut access = HeapGuard(g);
-        platform::mmu::release_table(self.raw, &mut access);
+        platform::mmu::release_table(self.raw, &mut FrameAccess);
     }
 }
@@ -0,0 +1,61 @@
+use core::ops::Range;
+
+use alloc::vec::Vec;
+
+/// 内核虚拟地址区间分配器，从区间起始处递增分配，释放的区间按首次适应复用
+pub(crate) struct VaAllocator {
+    end: usize,
+    cursor: usize,
+    free: Vec<Range<usize>>,
+}
+
+impl VaAllocator {
+    pub const fn new(region: Range<usize>) -> Self {
+        Self {
+            end: region.end,
+            cursor: region.start,
+            free: Vec::new(),
+        }
+    }
+
+    /// 分配 `size` 字节，`size` 需按页对齐
+    pub fn alloc(&mut self, size: usize) -> Option<Range<usize>> {
+        if let Some(i) = self.free.iter().position(|r| r.len() >= size) {
+            let r = &mut self.free[i];
+            let out = r.start..r.start + size;
+            r.start += size;
+            if r.start == r.end {
+                self.free.remove(i);
+            }
+            return Some(out);
+        }
+
+        let end = self.cursor.checked_add(size).filter(|&e| e <= self.end)?;
+        let out = self.cursor..end;
+        self.cursor = end;
+        Some(out)
+    }
+
+    pub fn free(&mut self, range: Range<usize>) {
+        let i = self.free.partition_point(|r| r.start < range.start);
+        self.free.insert(i, range);
+
+        // 与相邻区间合并
+        if i + 1 < self.free.len() && self.free[i].end == self.free[i + 1].start {
+            let next = self.free.remove(i + 1);
+            self.free[i].end = next.end;
+        }
+        if i > 0 && self.free[i - 1].end == self.free[i].start {
+            let cur = self.free.remove(i);
+            self.free[i - 1].end = cur.end;
+        }
+
+        // 归还到递增分配的末尾
+        if let Some(last) = self.free.last()
+            && last.end == self.cursor
+        {
+            self.cursor = last.start;
+            self.free.pop();
+        }
+    }
+}
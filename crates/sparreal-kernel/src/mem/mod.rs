@@ -1,7 +1,7 @@
 #![allow(unused)]
 
 use core::{
-    alloc::GlobalAlloc,
+    alloc::{GlobalAlloc, Layout},
     ops::Range,
     ptr::{NonNull, null_mut, slice_from_raw_parts_mut},
     sync::atomic::{AtomicUsize, Ordering},
@@ -14,11 +14,13 @@ use spin::{Mutex, Once};
 
 use crate::{
     globals::global_val,
-    hal_al::mmu::MapConfig,
+    hal_al::mmu::{MapConfig, PageFlags},
     irq::NoIrqGuard,
     mem::{
+        frame::{FRAMES, Zone},
         mmu::{AccessSetting, BootMemoryKind, BootRegion, CacheSetting, LINER_OFFSET},
         once::OnceStatic,
+        slab::Slab,
     },
     platform::{self, kstack_size},
     println,
@@ -28,22 +30,39 @@ pub use crate::platform::page_size;
 
 mod addr;
 mod cache;
+pub mod dma;
+pub mod frame;
+pub mod iommu;
+pub mod ioremap;
 // #[cfg(feature = "mmu")]
 pub mod mmu;
 pub mod once;
+pub mod oom;
 pub mod region;
+pub(crate) mod slab;
+pub mod stack;
+mod stats;
+#[cfg(feature = "alloc-track")]
+pub mod track;
+pub mod vmalloc;
 pub use addr::*;
+pub use stats::*;
 
 #[cfg(target_os = "none")]
 #[global_allocator]
 static ALLOCATOR: KAllocator = KAllocator {
     inner: Mutex::new(Heap::empty()),
+    slab: Slab::new(),
+    allocs: AtomicUsize::new(0),
+    frees: AtomicUsize::new(0),
 };
 
-static mut TMP_PAGE_ALLOC_ADDR: usize = 0;
-
 pub struct KAllocator {
     pub(crate) inner: Mutex<Heap<32>>,
+    /// 小对象从 slab 分配，不经过 `inner` 的锁
+    slab: Slab,
+    allocs: AtomicUsize,
+    frees: AtomicUsize,
 }
 
 impl KAllocator {
@@ -63,78 +82,125 @@ impl KAllocator {
 
         unsafe { g.add_to_heap(range.start as usize, range.end as usize) };
     }
-}
 
-unsafe impl GlobalAlloc for KAllocator {
-    unsafe fn alloc(&self, layout: core::alloc::Layout) -> *mut u8 {
-        let g = NoIrqGuard::new();
-        if let Ok(p) = self.inner.lock().alloc(layout) {
-            drop(g);
-            p.as_ptr()
-        } else {
-            drop(g);
-            null_mut()
+    fn try_alloc(&self, layout: Layout) -> Option<*mut u8> {
+        match slab::class_of(layout) {
+            Some(class) => self.slab.alloc(class, |l| self.heap_alloc(l)),
+            None => self.heap_alloc(layout),
         }
     }
 
-    unsafe fn dealloc(&self, ptr: *mut u8, layout: core::alloc::Layout) {
-        let g = NoIrqGuard::new();
-        self.inner
-            .lock()
-            .dealloc(unsafe { NonNull::new_unchecked(ptr) }, layout);
-        drop(g);
+    fn heap_alloc(&self, layout: Layout) -> Option<*mut u8> {
+        let _g = NoIrqGuard::new();
+        let mut heap = self.inner.lock();
+        loop {
+            if let Ok(p) = heap.alloc(layout) {
+                return Some(p.as_ptr());
+            }
+            if !grow_heap(&mut heap, layout) {
+                return None;
+            }
+        }
     }
-}
-
-pub(crate) fn init() {
-    let range = global_val().main_memory.clone();
-    mmu::init_with_tmp_table();
-
-    let mut start = VirtAddr::from(range.start.raw() + LINER_OFFSET);
-    let mut end = VirtAddr::from(range.end.raw() + LINER_OFFSET);
 
-    unsafe {
-        if TMP_PAGE_ALLOC_ADDR != 0 {
-            end = VirtAddr::from(TMP_PAGE_ALLOC_ADDR + LINER_OFFSET);
+    pub fn stats(&self) -> HeapStats {
+        let _g = NoIrqGuard::new();
+        let mut heap = self.inner.lock();
+        // 从大到小试探各阶空闲链表，第一个成功的即最大空闲块，分配后立即归还
+        let largest_free = (0..32)
+            .rev()
+            .map(|order| 1usize << order)
+            .find(|&size| {
+                let layout = Layout::from_size_align(size, 1).unwrap();
+                heap.alloc(layout).map(|p| heap.dealloc(p, layout)).is_ok()
+            })
+            .unwrap_or(0);
+
+        let total = heap.stats_total_bytes();
+        let used = heap.stats_alloc_actual();
+        HeapStats {
+            usage: UsageStats {
+                total,
+                used,
+                free: total - used,
+                largest_free,
+            },
+            requested: heap.stats_alloc_user(),
+            slab_cached: self.slab.cached_bytes(),
+            allocs: self.allocs.load(Ordering::Relaxed),
+            frees: self.frees.load(Ordering::Relaxed),
         }
     }
+}
 
-    println!("heap add memory [{}, {})", start, end);
-    #[cfg(target_os = "none")]
-    ALLOCATOR.add_to_heap(unsafe { &mut *slice_from_raw_parts_mut(start.into(), end - start) });
-
-    println!("heap initialized");
-
-    mmu::init();
+unsafe impl GlobalAlloc for KAllocator {
+    unsafe fn alloc(&self, layout: core::alloc::Layout) -> *mut u8 {
+        let mut round = 0;
+        loop {
+            if let Some(ptr) = self.try_alloc(layout) {
+                self.allocs.fetch_add(1, Ordering::Relaxed);
+                #[cfg(feature = "alloc-track")]
+                track::on_alloc(ptr, layout);
+                return ptr;
+            }
+            // 回收与处理策略可能释放内存或切换任务，须在持锁之外进行
+            if !oom::out_of_memory(layout, round) {
+                return null_mut();
+            }
+            round += 1;
+        }
+    }
 
-    add_all_ram();
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: core::alloc::Layout) {
+        #[cfg(feature = "alloc-track")]
+        track::on_dealloc(ptr);
+        match slab::class_of(layout) {
+            Some(class) => self.slab.dealloc(class, ptr),
+            None => {
+                let _g = NoIrqGuard::new();
+                self.inner
+                    .lock()
+                    .dealloc(unsafe { NonNull::new_unchecked(ptr) }, layout);
+            }
+        }
+        self.frees.fetch_add(1, Ordering::Relaxed);
+    }
+}
 
-    cache::init();
+/// 堆每次至少从页帧分配器扩充的大小
+const HEAP_GROW_MIN: usize = 1024 * 1024;
+
+/// 从页帧分配器取一块能满足 `layout` 的内存加入堆
+fn grow_heap(heap: &mut Heap<32>, layout: Layout) -> bool {
+    // 伙伴分配器按块大小对齐，按幂次对齐分配才能得到完整的一块
+    let size = layout
+        .size()
+        .max(layout.align())
+        .next_power_of_two()
+        .max(HEAP_GROW_MIN);
+    let Ok(frames) = Layout::from_size_align(size, size) else {
+        return false;
+    };
+    let Some(pa) = FRAMES.alloc(frames, Zone::High) else {
+        return false;
+    };
+    let start = frame::phys_to_virt(pa.raw());
+    unsafe { heap.add_to_heap(start, start + size) };
+    true
 }
 
-fn add_all_ram() {
+pub(crate) fn init() {
+    let ram = available_ram().expect("too many boot regions");
     let main = global_val().main_memory.clone();
+    frame::init(&ram, main);
 
-    for region in platform::boot_regions() {
-        if !matches!(region.kind, BootMemoryKind::Ram) {
-            continue;
-        }
-
-        if region.range.to_range().contains(&main.start) {
-            continue;
-        }
-
-        let start = VirtAddr::from(region.range.start.raw() + LINER_OFFSET);
-        let end = VirtAddr::from(region.range.end.raw() + LINER_OFFSET);
-        let len = end - start;
+    mmu::init();
 
-        println!("Heap add memory [{}, {})", start, end);
-        #[cfg(target_os = "none")]
-        ALLOCATOR.add_to_heap(unsafe { &mut *slice_from_raw_parts_mut(start.into(), len) });
-    }
+    cache::init();
 }
 
-pub(crate) fn find_main_memory() -> Option<BootRegion> {
+/// 去掉与非 RAM 区域重合部分后的全部 RAM
+fn available_ram() -> Option<heapless::Vec<PhysCRange, 64>> {
     let mut ram_regions = heapless::Vec::<_, 32>::new();
     let mut non_ram_regions = heapless::Vec::<_, 32>::new();
 
@@ -197,6 +263,13 @@ pub(crate) fn find_main_memory() -> Option<BootRegion> {
         }
     }
 
+    Some(available_regions)
+}
+
+pub(crate) fn find_main_memory() -> Option<BootRegion> {
+    let available_regions = available_ram()?;
+    let first_ram = platform::boot_regions().find(|r| matches!(r.kind, BootMemoryKind::Ram))?;
+
     // 选择范围大于16MB且地址最低的区域作为main memory
     const MIN_SIZE: usize = 16 * 1024 * 1024; // 16MB
     let mut best_region: Option<PhysCRange> = None;
@@ -223,7 +296,6 @@ pub(crate) fn find_main_memory() -> Option<BootRegion> {
         );
 
         // 创建主内存区域，使用第一个RAM区域的属性
-        let first_ram = ram_regions.first()?;
         Some(BootRegion {
             range: main_range,
             name: c"main memory".as_ptr() as _,
@@ -241,36 +313,31 @@ pub fn map(config: &MapConfig) -> Result<(), PagingError> {
     mmu::map(config)
 }
 
-pub fn iomap(paddr: PhysAddr, size: usize) -> NonNull<u8> {
-    let vaddr = VirtAddr::from(paddr.raw() + LINER_OFFSET);
-    match mmu::map(&MapConfig {
-        name: "iomap",
-        va_start: vaddr,
-        pa_start: paddr,
-        size,
-        access: AccessSetting::ReadWrite,
-        cache: CacheSetting::Device,
-    }) {
-        Ok(_) => {}
-        Err(e) => match e {
-            PagingError::AlreadyMapped => {}
-            _ => panic!("iomap failed: {:?}", e),
-        },
-    }
+/// 解除内核页表中 `[va_start, va_start + size)` 的映射
+pub fn unmap(va_start: VirtAddr, size: usize) -> Result<(), PagingError> {
+    mmu::unmap(va_start, size)
+}
 
-    let ptr: *mut u8 = vaddr.into();
-    unsafe { NonNull::new_unchecked(ptr) }
+/// 修改内核页表中 `[va_start, va_start + size)` 的访问权限
+pub fn protect(va_start: VirtAddr, size: usize, access: AccessSetting) -> Result<(), PagingError> {
+    mmu::protect(va_start, size, access)
+}
 
-    // unimplemented!();
-    // #[cfg(feature = "mmu")]
-    // {
-    //     mmu::iomap(paddr, _size)
-    // }
+/// 查询内核页表中 `va` 映射的物理地址与属性
+pub fn translate(va: VirtAddr) -> Option<(PhysAddr, PageFlags)> {
+    mmu::translate(va)
+}
 
-    // #[cfg(not(feature = "mmu"))]
-    // unsafe {
-    //     NonNull::new_unchecked(paddr.as_usize() as *mut u8)
-    // }
+/// 映射设备内存并一直保留，需要释放时使用 [`ioremap::ioremap`]
+///
+/// # Panics
+///
+/// 与已有映射的属性冲突或地址空间不足
+pub fn iomap(paddr: PhysAddr, size: usize) -> NonNull<u8> {
+    match ioremap::ioremap(paddr, size, CacheSetting::Device) {
+        Ok(region) => region.leak(),
+        Err(e) => panic!("iomap {paddr} failed: {e:?}"),
+    }
 }
 
 #[cfg(test)]
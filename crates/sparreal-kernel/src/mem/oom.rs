@@ -0,0 +1,146 @@
+//! 内核堆内存不足时的处理。
+//!
+//! 分配失败后先依次调用注册的回收函数（例如驱动丢弃缓存），回收后重试；
+//! 仍然失败时分配器返回空指针，可失败的分配（如 `try_reserve`）由调用者处理。
+//! 不可失败的分配进入 `alloc_error_handler`，打印内存报告并按 [`OomPolicy`] 处理。
+
+use core::{
+    alloc::Layout,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use spin::Mutex;
+
+use crate::{irq::NoIrqGuard, platform, println, syscall::ENOMEM, task};
+
+/// 可注册的回收函数个数上限
+pub const MAX_RECLAIMERS: usize = 16;
+
+/// 回收函数，参数为本次分配的字节数，返回释放的字节数
+pub type ReclaimFn = fn(needed: usize) -> usize;
+
+static RECLAIMERS: Mutex<heapless::Vec<Reclaimer, MAX_RECLAIMERS>> =
+    Mutex::new(heapless::Vec::new());
+static POLICY: Mutex<OomPolicy> = Mutex::new(OomPolicy::Retry { attempts: 1 });
+/// 正在处理内存不足的 CPU 编号加一，0 表示空闲
+static HANDLING: AtomicUsize = AtomicUsize::new(0);
+
+/// 回收与重试的方式，以及不可失败的分配最终失败时的处理
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OomPolicy {
+    /// 回收一轮，若有释放则重试一次，不可失败的分配仍失败则 panic
+    Panic,
+    /// 回收一轮，若有释放则重试一次，不可失败的分配仍失败则以 `-ENOMEM` 结束当前任务。
+    ///
+    /// 不在独立内核栈的任务中或屏蔽中断时则 panic。
+    KillTask,
+    /// 每轮回收后重试，至多 `attempts` 轮，不可失败的分配仍失败则 panic
+    Retry { attempts: usize },
+}
+
+#[derive(Clone, Copy)]
+struct Reclaimer {
+    name: &'static str,
+    f: ReclaimFn,
+}
+
+#[derive(Debug)]
+pub enum OomError {
+    /// 回收函数已满 [`MAX_RECLAIMERS`] 个
+    TooManyReclaimers,
+}
+
+/// 注册回收函数。
+///
+/// 回收函数在屏蔽中断的情况下调用，其中的分配失败不会再次触发回收。
+pub fn register_reclaim(name: &'static str, f: ReclaimFn) -> Result<(), OomError> {
+    let _g = NoIrqGuard::new();
+    RECLAIMERS
+        .lock()
+        .push(Reclaimer { name, f })
+        .map_err(|_| OomError::TooManyReclaimers)
+}
+
+/// 注销 `name` 对应的回收函数
+pub fn unregister_reclaim(name: &'static str) {
+    let _g = NoIrqGuard::new();
+    RECLAIMERS.lock().retain(|r| r.name != name);
+}
+
+pub fn set_policy(policy: OomPolicy) {
+    *POLICY.lock() = policy;
+}
+
+pub fn policy() -> OomPolicy {
+    *POLICY.lock()
+}
+
+/// 依次调用所有回收函数，返回释放的总字节数
+pub fn reclaim(needed: usize) -> usize {
+    let reclaimers = {
+        let _g = NoIrqGuard::new();
+        RECLAIMERS.lock().clone()
+    };
+    reclaimers
+        .iter()
+        .map(|r| {
+            let freed = (r.f)(needed);
+            debug!("reclaim {}: freed {freed:#x} bytes", r.name);
+            freed
+        })
+        .sum()
+}
+
+/// 第 `round` 次分配 `layout` 失败后由分配器调用，返回是否重试。
+///
+/// 只回收，不结束任务：可失败的分配须能得到空指针。
+pub(super) fn out_of_memory(layout: Layout, round: usize) -> bool {
+    let cpu = platform::cpu_id() + 1;
+    let g = NoIrqGuard::new();
+    // 回收函数内部分配失败时直接返回空指针
+    if HANDLING.load(Ordering::Acquire) == cpu {
+        return false;
+    }
+    while HANDLING
+        .compare_exchange_weak(0, cpu, Ordering::Acquire, Ordering::Relaxed)
+        .is_err()
+    {
+        core::hint::spin_loop();
+    }
+
+    let freed = reclaim(layout.size());
+    let retry = match policy() {
+        OomPolicy::Retry { attempts } => round < attempts,
+        OomPolicy::Panic | OomPolicy::KillTask => round == 0 && freed > 0,
+    };
+    HANDLING.store(0, Ordering::Release);
+    drop(g);
+    retry
+}
+
+/// 不可失败的分配在回收重试后仍失败，由 `alloc_error_handler` 调用
+pub(crate) fn alloc_error(layout: Layout) -> ! {
+    let policy = policy();
+    println!("out of memory allocating {layout:?}, policy {policy:?}");
+    println!("{}", super::usage());
+    match policy {
+        OomPolicy::KillTask => kill_current(layout, platform::irq_all_is_enabled()),
+        OomPolicy::Panic | OomPolicy::Retry { .. } => {
+            panic!("out of memory allocating {layout:?}")
+        }
+    }
+}
+
+fn kill_current(layout: Layout, irq_enabled: bool) -> ! {
+    // 主任务与空闲任务不能结束，屏蔽中断时可能处于中断上下文
+    match task::try_current() {
+        Some(tcb) if irq_enabled && tcb.stack.is_some() => {
+            error!(
+                "task {:?} ({}) killed: out of memory allocating {layout:?}",
+                tcb.pid, tcb.name
+            );
+            task::exit(-ENOMEM)
+        }
+        _ => panic!("out of memory allocating {layout:?}"),
+    }
+}
@@ -0,0 +1,171 @@
+//! 小对象的 slab 缓存。
+//!
+//! 每个大小类别有一个全局仓库，对象从主堆申请的大块内存中切分。每个CPU为每个类别持有一个弹夹，
+//! 分配与释放通常只访问本CPU的弹夹，弹夹空或满时才与仓库成批交换，避免每次都竞争主堆的锁。
+//! 切分出的内存不归还主堆。
+
+use core::alloc::Layout;
+
+use spin::Mutex;
+
+use crate::{globals::cpu_global_meybeuninit, irq::NoIrqGuard};
+
+/// 最小类别的对象大小
+const MIN_SIZE: usize = 16;
+/// 大小类别 16, 32, ..., 1024
+pub const CLASSES: usize = 7;
+/// 能由 slab 分配的最大对象
+pub const MAX_SIZE: usize = MIN_SIZE << (CLASSES - 1);
+/// 每次从主堆申请的大小
+const CHUNK_SIZE: usize = 16 * 1024;
+/// 每个弹夹最多缓存的对象数
+const MAGAZINE_SIZE: usize = 32;
+
+/// `layout` 对应的大小类别，过大时为 `None`
+pub(crate) fn class_of(layout: Layout) -> Option<usize> {
+    let size = layout.size().max(layout.align()).max(MIN_SIZE);
+    (size <= MAX_SIZE).then(|| (size.next_power_of_two() / MIN_SIZE).trailing_zeros() as usize)
+}
+
+fn object_size(class: usize) -> usize {
+    MIN_SIZE << class
+}
+
+/// 空闲对象组成的单链表，链接指针存放在对象的首个字中
+struct Depot {
+    head: usize,
+    len: usize,
+}
+
+impl Depot {
+    const fn new() -> Self {
+        Self { head: 0, len: 0 }
+    }
+
+    fn push(&mut self, obj: usize) {
+        unsafe { (obj as *mut usize).write(self.head) };
+        self.head = obj;
+        self.len += 1;
+    }
+
+    fn pop(&mut self) -> Option<usize> {
+        if self.head == 0 {
+            return None;
+        }
+        let obj = self.head;
+        self.head = unsafe { (obj as *const usize).read() };
+        self.len -= 1;
+        Some(obj)
+    }
+
+    /// 从主堆申请一块内存切分为 `class` 类别的对象
+    fn grow(&mut self, class: usize, alloc: impl FnOnce(Layout) -> Option<*mut u8>) -> bool {
+        let layout = Layout::from_size_align(CHUNK_SIZE, MAX_SIZE).unwrap();
+        let Some(chunk) = alloc(layout) else {
+            return false;
+        };
+        let size = object_size(class);
+        for offset in (0..CHUNK_SIZE).step_by(size).rev() {
+            self.push(chunk as usize + offset);
+        }
+        true
+    }
+}
+
+struct Magazine {
+    objs: [usize; MAGAZINE_SIZE],
+    len: usize,
+}
+
+impl Magazine {
+    const fn new() -> Self {
+        Self {
+            objs: [0; MAGAZINE_SIZE],
+            len: 0,
+        }
+    }
+}
+
+/// 每个CPU的弹夹
+pub(crate) struct CpuCache([Mutex<Magazine>; CLASSES]);
+
+impl Default for CpuCache {
+    fn default() -> Self {
+        Self([const { Mutex::new(Magazine::new()) }; CLASSES])
+    }
+}
+
+pub(crate) struct Slab {
+    depots: [Mutex<Depot>; CLASSES],
+}
+
+impl Slab {
+    pub(crate) const fn new() -> Self {
+        Self {
+            depots: [const { Mutex::new(Depot::new()) }; CLASSES],
+        }
+    }
+
+    /// 分配 `class` 类别的对象，仓库为空时用 `alloc` 从主堆申请
+    pub(crate) fn alloc(
+        &self,
+        class: usize,
+        alloc: impl FnOnce(Layout) -> Option<*mut u8>,
+    ) -> Option<*mut u8> {
+        let _g = NoIrqGuard::new();
+        // PerCPU 初始化前直接使用仓库
+        let Some(cpu) = cpu_global_meybeuninit() else {
+            let mut depot = self.depots[class].lock();
+            if depot.len == 0 && !depot.grow(class, alloc) {
+                return None;
+            }
+            return depot.pop().map(|obj| obj as *mut u8);
+        };
+
+        let mut mag = cpu.slab.0[class].lock();
+        if mag.len == 0 {
+            let mut depot = self.depots[class].lock();
+            if depot.len == 0 && !depot.grow(class, alloc) {
+                return None;
+            }
+            // 取一半弹夹，留出空位给随后的释放
+            while mag.len < MAGAZINE_SIZE / 2 {
+                let Some(obj) = depot.pop() else { break };
+                let len = mag.len;
+                mag.objs[len] = obj;
+                mag.len += 1;
+            }
+        }
+        mag.len -= 1;
+        Some(mag.objs[mag.len] as *mut u8)
+    }
+
+    /// 释放 `class` 类别的对象，可以在任意CPU上释放
+    pub(crate) fn dealloc(&self, class: usize, ptr: *mut u8) {
+        let _g = NoIrqGuard::new();
+        let Some(cpu) = cpu_global_meybeuninit() else {
+            self.depots[class].lock().push(ptr as usize);
+            return;
+        };
+
+        let mut mag = cpu.slab.0[class].lock();
+        if mag.len == MAGAZINE_SIZE {
+            let mut depot = self.depots[class].lock();
+            while mag.len > MAGAZINE_SIZE / 2 {
+                mag.len -= 1;
+                depot.push(mag.objs[mag.len]);
+            }
+        }
+        let len = mag.len;
+        mag.objs[len] = ptr as usize;
+        mag.len += 1;
+    }
+
+    /// 缓存在仓库中的空闲字节数，不含各CPU弹夹
+    pub(crate) fn cached_bytes(&self) -> usize {
+        let _g = NoIrqGuard::new();
+        (0..CLASSES)
+            .map(|class| self.depots[class].lock().len * object_size(class))
+            .sum()
+    }
+}
@@ -0,0 +1,80 @@
+//! 内核栈，从专用的虚拟地址区域分配。
+//!
+//! 每个栈的栈底下方留一页不映射，栈溢出时触发缺页异常而不会覆盖其他内存。
+
+use core::{
+    ops::Range,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use page_table_generic::PagingError;
+use spin::Mutex;
+
+use super::{
+    align_up,
+    mmu::va::VaAllocator,
+    page_size,
+    vmalloc::{map_pages, unmap_pages},
+};
+use crate::irq::NoIrqGuard;
+
+/// 内核栈所在的虚拟地址区域
+pub const STACK_REGION: Range<usize> = 0xffff_4000_0000_0000..0xffff_4010_0000_0000;
+
+static AREA: Mutex<VaAllocator> = Mutex::new(VaAllocator::new(STACK_REGION));
+/// 主CPU启动栈的保护页
+static BOOT_GUARD: AtomicUsize = AtomicUsize::new(0);
+
+/// 带保护页的内核栈，释放时解除映射。
+///
+/// 栈的每页单独分配，不要求物理连续。
+pub struct KernelStack {
+    area: Range<usize>,
+}
+
+impl KernelStack {
+    pub fn new(size: usize) -> Result<Self, PagingError> {
+        let size = align_up(size, page_size());
+        let area = {
+            let _g = NoIrqGuard::new();
+            AREA.lock().alloc(page_size() + size)
+        }
+        .ok_or(PagingError::NoMemory)?;
+
+        if let Err(e) = map_pages(area.start + page_size(), size, "kstack") {
+            let _g = NoIrqGuard::new();
+            AREA.lock().free(area);
+            return Err(e);
+        }
+        Ok(Self { area })
+    }
+
+    pub fn bottom(&self) -> usize {
+        self.area.start + page_size()
+    }
+
+    pub fn top(&self) -> usize {
+        self.area.end
+    }
+}
+
+impl Drop for KernelStack {
+    fn drop(&mut self) {
+        unmap_pages(self.bottom(), self.top() - self.bottom());
+        let _g = NoIrqGuard::new();
+        AREA.lock().free(self.area.clone());
+    }
+}
+
+/// 记录主CPU启动栈的保护页
+pub(crate) fn set_boot_guard(va: usize) {
+    BOOT_GUARD.store(va, Ordering::Relaxed);
+}
+
+/// `addr` 是否位于内核栈的保护页，用于在缺页异常中识别栈溢出。
+///
+/// 栈区域中除已映射的栈外均不映射，落在其中的访问都视为越过栈边界。
+pub fn is_stack_guard(addr: usize) -> bool {
+    let boot = BOOT_GUARD.load(Ordering::Relaxed);
+    STACK_REGION.contains(&addr) || (boot != 0 && (boot..boot + page_size()).contains(&addr))
+}
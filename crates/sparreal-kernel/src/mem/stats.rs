@@ -0,0 +1,149 @@
+use core::{fmt, ops::Range};
+
+use alloc::vec::Vec;
+
+use super::{
+    PhysAddr, VirtAddr,
+    frame::{FRAMES, Zone, ZoneStats},
+    mmu::{AccessSetting, BootMemoryKind, CacheSetting},
+    page_size,
+};
+use crate::platform;
+
+/// 内存用量，单位为字节
+#[derive(Debug, Default, Clone, Copy)]
+pub struct UsageStats {
+    pub total: usize,
+    pub used: usize,
+    pub free: usize,
+    /// 最大的连续空闲块
+    pub largest_free: usize,
+}
+
+impl From<ZoneStats> for UsageStats {
+    fn from(value: ZoneStats) -> Self {
+        Self {
+            total: value.total * page_size(),
+            used: (value.total - value.free) * page_size(),
+            free: value.free * page_size(),
+            largest_free: value.largest_free * page_size(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct HeapStats {
+    /// `used` 包含伙伴分配器取整的部分
+    pub usage: UsageStats,
+    /// 直接向主堆申请的字节数，小对象按 slab 整块计
+    pub requested: usize,
+    /// slab 仓库中缓存的空闲字节数，计入 `used`
+    pub slab_cached: usize,
+    /// 累计分配次数
+    pub allocs: usize,
+    /// 累计释放次数
+    pub frees: usize,
+}
+
+/// 启动时映射的内存区域
+#[derive(Debug, Clone)]
+pub struct RegionStats {
+    pub name: &'static str,
+    pub kind: BootMemoryKind,
+    pub phys: Range<PhysAddr>,
+    pub virt: VirtAddr,
+    pub access: AccessSetting,
+    pub cache: CacheSetting,
+}
+
+#[derive(Debug, Clone)]
+pub struct MemStats {
+    pub heap: HeapStats,
+    pub dma32: UsageStats,
+    pub high: UsageStats,
+    pub regions: Vec<RegionStats>,
+}
+
+/// 收集堆、页帧与启动映射的当前状态
+pub fn stats() -> MemStats {
+    let mut stats = usage();
+    stats.regions = platform::boot_regions()
+        .map(|r| RegionStats {
+            name: r.name(),
+            kind: r.kind,
+            phys: r.range.to_range(),
+            virt: (r.range.start.raw() + r.va_offset()).into(),
+            access: r.access,
+            cache: r.cache,
+        })
+        .collect();
+    stats
+}
+
+/// 只收集堆与页帧的用量，不分配内存，可在内存不足时调用
+pub fn usage() -> MemStats {
+    #[cfg(target_os = "none")]
+    let heap = super::ALLOCATOR.stats();
+    #[cfg(not(target_os = "none"))]
+    let heap = HeapStats::default();
+
+    MemStats {
+        heap,
+        dma32: FRAMES.stats(Zone::Dma32).into(),
+        high: FRAMES.stats(Zone::High).into(),
+        regions: Vec::new(),
+    }
+}
+
+struct Kib(usize);
+
+impl fmt::Display for Kib {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:>10} kB", self.0 / 1024)
+    }
+}
+
+fn write_usage(f: &mut fmt::Formatter<'_>, name: &str, u: &UsageStats) -> fmt::Result {
+    writeln!(
+        f,
+        "{name:<8} total {} used {} free {} largest {}",
+        Kib(u.total),
+        Kib(u.used),
+        Kib(u.free),
+        Kib(u.largest_free)
+    )
+}
+
+impl fmt::Display for MemStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_usage(f, "Heap", &self.heap.usage)?;
+        writeln!(
+            f,
+            "         requested {} slab cached {} allocs {} frees {}",
+            Kib(self.heap.requested),
+            Kib(self.heap.slab_cached),
+            self.heap.allocs,
+            self.heap.frees
+        )?;
+        write_usage(f, "DMA32", &self.dma32)?;
+        write_usage(f, "High", &self.high)?;
+        if self.regions.is_empty() {
+            return Ok(());
+        }
+        writeln!(f, "Boot regions:")?;
+        for r in &self.regions {
+            writeln!(
+                f,
+                "  [{:<16}] [{:#x}, {:#x}) -> {:#x} {:?} {:?} {:?}",
+                r.name,
+                r.phys.start.raw(),
+                r.phys.end.raw(),
+                r.virt.raw(),
+                r.kind,
+                r.access,
+                r.cache
+            )?;
+        }
+        Ok(())
+    }
+}
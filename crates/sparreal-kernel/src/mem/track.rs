@@ -0,0 +1,232 @@
+//! 堆分配跟踪，记录每个存活分配的大小、所属任务与调用栈。
+//!
+//! 记录存放在单独的堆中，自身不被跟踪，也不占用内核堆。
+
+use core::{
+    alloc::{AllocError, Allocator, Layout},
+    fmt,
+    ops::Range,
+    ptr::NonNull,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use alloc::{collections::btree_map::BTreeMap, vec::Vec};
+use buddy_system_allocator::Heap;
+use spin::Mutex;
+
+use super::grow_heap;
+use crate::{
+    globals::cpu_global_meybeuninit,
+    irq::NoIrqGuard,
+    platform, println,
+    task::{self, Pid},
+};
+
+/// 每条记录保存的返回地址个数
+pub const BACKTRACE_DEPTH: usize = 8;
+
+static META: MetaHeap = MetaHeap(Mutex::new(Heap::empty()));
+static LIVE: Mutex<Option<BTreeMap<usize, AllocRecord, &'static MetaHeap>>> = Mutex::new(None);
+static SEQ: AtomicU64 = AtomicU64::new(0);
+
+/// 存放跟踪记录的堆，同样从页帧分配器扩充
+pub struct MetaHeap(Mutex<Heap<32>>);
+
+unsafe impl Allocator for MetaHeap {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        let _g = NoIrqGuard::new();
+        let mut heap = self.0.lock();
+        loop {
+            if let Ok(p) = heap.alloc(layout) {
+                return Ok(NonNull::slice_from_raw_parts(p, layout.size()));
+            }
+            if !grow_heap(&mut heap, layout) {
+                return Err(AllocError);
+            }
+        }
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        let _g = NoIrqGuard::new();
+        self.0.lock().dealloc(ptr, layout);
+    }
+}
+
+/// 一个存活的堆分配
+#[derive(Clone)]
+pub struct AllocRecord {
+    pub addr: usize,
+    pub size: usize,
+    /// 分配时的当前任务，任务初始化前为 `None`
+    pub pid: Option<Pid>,
+    /// 分配序号，区分先后复用同一地址的分配
+    pub seq: u64,
+    backtrace: [usize; BACKTRACE_DEPTH],
+    depth: usize,
+}
+
+impl AllocRecord {
+    /// 分配处的返回地址，由内向外
+    pub fn backtrace(&self) -> &[usize] {
+        &self.backtrace[..self.depth]
+    }
+}
+
+impl fmt::Debug for AllocRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "#{} {:#x} size {:#x} pid {:?}",
+            self.seq, self.addr, self.size, self.pid
+        )?;
+        for pc in self.backtrace() {
+            write!(f, "\n    {pc:#x}")?;
+        }
+        Ok(())
+    }
+}
+
+/// 当前任务的内核栈
+fn current_stack(pid: &mut Option<Pid>) -> Option<Range<usize>> {
+    match task::try_current() {
+        Some(tcb) => {
+            *pid = Some(tcb.pid);
+            match &tcb.stack {
+                Some(stack) => Some(stack.bottom()..stack.top()),
+                // 主任务运行在启动栈上
+                None => cpu_global_meybeuninit().map(|c| c.stack.start.raw()..c.stack.end.raw()),
+            }
+        }
+        None => None,
+    }
+}
+
+pub(super) fn on_alloc(ptr: *mut u8, layout: Layout) {
+    let mut pid = None;
+    let mut backtrace = [0; BACKTRACE_DEPTH];
+    let depth = current_stack(&mut pid)
+        .map(|stack| platform::backtrace(stack, &mut backtrace))
+        .unwrap_or(0);
+
+    let record = AllocRecord {
+        addr: ptr as usize,
+        size: layout.size(),
+        pid,
+        seq: SEQ.fetch_add(1, Ordering::Relaxed),
+        backtrace,
+        depth,
+    };
+    let _g = NoIrqGuard::new();
+    LIVE.lock()
+        .get_or_insert_with(|| BTreeMap::new_in(&META))
+        .insert(record.addr, record);
+}
+
+/// 须在归还内存前调用，否则地址可能已被重新分配并记录
+pub(super) fn on_dealloc(ptr: *mut u8) {
+    let _g = NoIrqGuard::new();
+    if let Some(live) = LIVE.lock().as_mut() {
+        live.remove(&(ptr as usize));
+    }
+}
+
+/// 某一时刻全部存活分配的副本，按分配顺序排列
+pub struct Snapshot {
+    records: Vec<AllocRecord, &'static MetaHeap>,
+}
+
+/// 获取当前存活分配的快照
+pub fn snapshot() -> Snapshot {
+    let _g = NoIrqGuard::new();
+    let live = LIVE.lock();
+    let mut records = Vec::new_in(&META);
+    if let Some(live) = live.as_ref() {
+        records.reserve(live.len());
+        records.extend(live.values().cloned());
+    }
+    drop(live);
+    records.sort_unstable_by_key(|r| r.seq);
+    Snapshot { records }
+}
+
+/// 打印当前全部存活分配
+pub fn dump() {
+    println!("{}", snapshot());
+}
+
+impl Snapshot {
+    pub fn records(&self) -> &[AllocRecord] {
+        &self.records
+    }
+
+    pub fn total_bytes(&self) -> usize {
+        self.records.iter().map(|r| r.size).sum()
+    }
+
+    /// 与更晚的快照 `later` 比较
+    pub fn diff(&self, later: &Snapshot) -> SnapshotDiff {
+        let mut allocated = Vec::new_in(&META);
+        let mut freed = Vec::new_in(&META);
+        let (mut a, mut b) = (
+            self.records.iter().peekable(),
+            later.records.iter().peekable(),
+        );
+        loop {
+            match (a.peek(), b.peek()) {
+                (Some(x), Some(y)) if x.seq == y.seq => {
+                    a.next();
+                    b.next();
+                }
+                (Some(x), Some(y)) if x.seq < y.seq => freed.push(a.next().unwrap().clone()),
+                (_, Some(_)) => allocated.push(b.next().unwrap().clone()),
+                (Some(_), None) => freed.push(a.next().unwrap().clone()),
+                (None, None) => break,
+            }
+        }
+        SnapshotDiff { allocated, freed }
+    }
+}
+
+impl fmt::Display for Snapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} live allocations, {:#x} bytes",
+            self.records.len(),
+            self.total_bytes()
+        )?;
+        for r in &self.records {
+            writeln!(f, "  {r:?}")?;
+        }
+        Ok(())
+    }
+}
+
+/// 两次快照之间的变化
+pub struct SnapshotDiff {
+    /// 之后分配且仍存活
+    pub allocated: Vec<AllocRecord, &'static MetaHeap>,
+    /// 之前存活而之后已释放
+    pub freed: Vec<AllocRecord, &'static MetaHeap>,
+}
+
+impl SnapshotDiff {
+    /// 由 `pid` 分配且仍存活的分配
+    pub fn allocated_by(&self, pid: Pid) -> impl Iterator<Item = &AllocRecord> {
+        self.allocated.iter().filter(move |r| r.pid == Some(pid))
+    }
+}
+
+impl fmt::Display for SnapshotDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "+{} allocations:", self.allocated.len())?;
+        for r in &self.allocated {
+            writeln!(f, "  {r:?}")?;
+        }
+        writeln!(f, "-{} allocations:", self.freed.len())?;
+        for r in &self.freed {
+            writeln!(f, "  {r:?}")?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,111 @@
+//! 虚拟地址连续、物理页分散的内核内存。
+//!
+//! 每页单独从页帧分配器分配，再映射到专用的虚拟地址区域，大块分配不受物理内存碎片影响。
+
+use core::{alloc::Layout, ops::Range, ptr::NonNull};
+
+use alloc::{collections::btree_map::BTreeMap, vec::Vec};
+use page_table_generic::PagingError;
+use spin::Mutex;
+
+use super::{
+    PhysAddr, VirtAddr, align_up,
+    frame::{FRAMES, Zone},
+    mmu::{self, AccessSetting, CacheSetting, va::VaAllocator},
+    page_size,
+};
+use crate::{hal_al::mmu::MapConfig, irq::NoIrqGuard};
+
+/// vmalloc 所用的虚拟地址区域
+pub const VMALLOC_REGION: Range<usize> = 0xffff_0000_0000_0000..0xffff_1000_0000_0000;
+
+static AREA: Mutex<VaAllocator> = Mutex::new(VaAllocator::new(VMALLOC_REGION));
+/// 起始地址到占用的地址区间，区间末尾含一页保护页
+static ALLOCATIONS: Mutex<BTreeMap<usize, Range<usize>>> = Mutex::new(BTreeMap::new());
+
+/// 分配 `size` 字节虚拟地址连续的内存，按页取整，内容未初始化。
+///
+/// 末尾留一页不映射，越界访问会触发缺页异常。
+pub fn vmalloc(size: usize) -> Option<NonNull<u8>> {
+    if size == 0 {
+        return None;
+    }
+    let size = align_up(size, page_size());
+    let area = {
+        let _g = NoIrqGuard::new();
+        AREA.lock().alloc(size.checked_add(page_size())?)?
+    };
+
+    if map_pages(area.start, size, "vmalloc").is_err() {
+        let _g = NoIrqGuard::new();
+        AREA.lock().free(area);
+        return None;
+    }
+
+    let ptr = area.start as *mut u8;
+    let _g = NoIrqGuard::new();
+    ALLOCATIONS.lock().insert(area.start, area);
+    NonNull::new(ptr)
+}
+
+/// 释放 [`vmalloc`] 分配的内存
+///
+/// # Safety
+///
+/// `ptr` 必须由 [`vmalloc`] 返回，且此后不再访问
+pub unsafe fn vfree(ptr: NonNull<u8>) {
+    let start = ptr.as_ptr() as usize;
+    let area = {
+        let _g = NoIrqGuard::new();
+        ALLOCATIONS.lock().remove(&start)
+    }
+    .unwrap_or_else(|| panic!("vfree of unknown address {start:#x}"));
+
+    unmap_pages(area.start, area.end - area.start - page_size());
+    let _g = NoIrqGuard::new();
+    AREA.lock().free(area);
+}
+
+fn page_layout() -> Layout {
+    Layout::from_size_align(page_size(), page_size()).unwrap()
+}
+
+/// 逐页分配物理页并映射到 `[va, va + size)`，失败时撤销已完成的部分
+pub(crate) fn map_pages(va: usize, size: usize, name: &'static str) -> Result<(), PagingError> {
+    for offset in (0..size).step_by(page_size()) {
+        let res = FRAMES
+            .alloc(page_layout(), Zone::High)
+            .ok_or(PagingError::NoMemory)
+            .and_then(|pa| {
+                mmu::map(&MapConfig {
+                    name,
+                    va_start: (va + offset).into(),
+                    pa_start: pa,
+                    size: page_size(),
+                    access: AccessSetting::ReadWrite,
+                    cache: CacheSetting::Normal,
+                })
+                .inspect_err(|_| FRAMES.dealloc(pa, page_layout()))
+            });
+        if let Err(e) = res {
+            unmap_pages(va, offset);
+            return Err(e);
+        }
+    }
+    Ok(())
+}
+
+/// 解除 [`map_pages`] 的映射并归还物理页
+pub(crate) fn unmap_pages(va: usize, size: usize) {
+    let frames: Vec<PhysAddr> = (0..size)
+        .step_by(page_size())
+        .filter_map(|offset| mmu::translate(VirtAddr::from(va + offset)).map(|(pa, _)| pa))
+        .collect();
+    if let Err(e) = mmu::unmap(va.into(), size) {
+        panic!("unmap [{va:#x}, +{size:#x}) failed: {e:?}");
+    }
+    // 映射与 TLB 均已清除后才能归还，否则页被重新分配后仍可经旧映射访问
+    for pa in frames {
+        FRAMES.dealloc(pa, page_layout());
+    }
+}
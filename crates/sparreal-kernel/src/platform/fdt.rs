@@ -10,7 +10,10 @@ use rdrive::Phandle;
 
 use super::{CPUInfo, SerialPort};
 use crate::mem::PhysAddr;
-use crate::{irq::IrqInfo, mem::mmu::LINER_OFFSET};
+use crate::{
+    irq::{self, IrqInfo},
+    mem::mmu::LINER_OFFSET,
+};
 
 #[derive(Clone)]
 pub struct Fdt(PhysAddr);
@@ -108,6 +111,13 @@ impl GetIrqConfig for Node<'_> {
 
 fn parse_irq_config(parent: Phandle, interrupts: &[Vec<u32>]) -> Option<IrqInfo> {
     let irq_parent = rdrive::fdt_phandle_to_device_id(parent)?;
+    if let Some(cascade) = irq::cascade::get(irq_parent) {
+        let cfgs = interrupts
+            .iter()
+            .filter_map(|raw| cascade.parse_fdt(raw))
+            .collect();
+        return Some(IrqInfo { irq_parent, cfgs });
+    }
     let parent = rdrive::get::<rdif_intc::Intc>(irq_parent).expect("Intc not found");
     let parse_fun = { parent.lock().unwrap().parse_dtb_fn()? };
 
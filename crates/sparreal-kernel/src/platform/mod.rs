@@ -8,7 +8,7 @@ use log::error;
 use fdt::Fdt;
 use rdrive::register::DriverRegister;
 
-use crate::globals::global_val;
+use crate::globals::{self, global_val};
 use crate::mem::PhysAddr;
 use crate::mem::mmu::BootRegion;
 use crate::{hal_al, platform};
@@ -92,6 +92,20 @@ pub fn cpu_hard_id() -> CPUHardId {
     CPUHardId(platform::cpu_id())
 }
 
+/// Soft id of the CPU running this code.
+pub fn cpu_current_id() -> CPUId {
+    CPUId::from(cpu_hard_id())
+}
+
+/// CPUs that have finished bring-up and are handling interrupts.
+pub fn cpu_online_list() -> Vec<CPUId> {
+    globals::cpu_online_list()
+}
+
+pub fn cpu_is_online(cpu: CPUId) -> bool {
+    globals::cpu_global_by_id(cpu).is_some_and(|c| c.is_online())
+}
+
 pub fn platform_name() -> String {
     match &global_val().platform_info {
         PlatformInfoKind::DeviceTree(fdt) => fdt.model_name().unwrap_or_default(),
@@ -248,6 +262,12 @@ pub struct CPUHardId(usize);
 //     }
 // }
 
+impl From<CPUHardId> for usize {
+    fn from(value: CPUHardId) -> Self {
+        value.0
+    }
+}
+
 impl Display for CPUHardId {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         write!(f, "{:#x}", self.0)
@@ -0,0 +1,73 @@
+use core::{
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use super::{MutexGuard, WaitQueue};
+
+/// 条件变量，配合 [`super::Mutex`] 使用，可能被虚假唤醒
+pub struct Condvar {
+    seq: AtomicUsize,
+    wq: WaitQueue,
+}
+
+impl Condvar {
+    pub const fn new() -> Self {
+        Self {
+            seq: AtomicUsize::new(0),
+            wq: WaitQueue::new(),
+        }
+    }
+
+    /// 释放锁并挂起，被通知后重新加锁
+    pub fn wait<'a, T: ?Sized>(&self, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+        let seq = self.seq.load(Ordering::Acquire);
+        let mutex = guard.mutex;
+        drop(guard);
+        self.wq
+            .wait_until(|| self.seq.load(Ordering::Acquire) != seq);
+        mutex.lock()
+    }
+
+    /// 同 [`Condvar::wait`]，第二个返回值表示是否超时
+    pub fn wait_timeout<'a, T: ?Sized>(
+        &self,
+        guard: MutexGuard<'a, T>,
+        timeout: Duration,
+    ) -> (MutexGuard<'a, T>, bool) {
+        let seq = self.seq.load(Ordering::Acquire);
+        let mutex = guard.mutex;
+        drop(guard);
+        let notified = self
+            .wq
+            .wait_until_timeout(timeout, || self.seq.load(Ordering::Acquire) != seq);
+        (mutex.lock(), !notified)
+    }
+
+    pub fn wait_while<'a, T: ?Sized>(
+        &self,
+        mut guard: MutexGuard<'a, T>,
+        mut condition: impl FnMut(&mut T) -> bool,
+    ) -> MutexGuard<'a, T> {
+        while condition(&mut *guard) {
+            guard = self.wait(guard);
+        }
+        guard
+    }
+
+    pub fn notify_one(&self) {
+        self.seq.fetch_add(1, Ordering::Release);
+        self.wq.notify_one();
+    }
+
+    pub fn notify_all(&self) {
+        self.seq.fetch_add(1, Ordering::Release);
+        self.wq.notify_all();
+    }
+}
+
+impl Default for Condvar {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,46 @@
+use core::{
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+use super::WaitQueue;
+
+/// 一次性事件，`set` 后所有等待者返回，之后的 `wait` 立即返回
+pub struct Event {
+    set: AtomicBool,
+    wq: WaitQueue,
+}
+
+impl Event {
+    pub const fn new() -> Self {
+        Self {
+            set: AtomicBool::new(false),
+            wq: WaitQueue::new(),
+        }
+    }
+
+    /// 可在中断上下文中调用
+    pub fn set(&self) {
+        self.set.store(true, Ordering::Release);
+        self.wq.notify_all();
+    }
+
+    pub fn is_set(&self) -> bool {
+        self.set.load(Ordering::Acquire)
+    }
+
+    pub fn wait(&self) {
+        self.wq.wait_until(|| self.is_set());
+    }
+
+    /// 超时返回 `false`
+    pub fn wait_timeout(&self, timeout: Duration) -> bool {
+        self.wq.wait_until_timeout(timeout, || self.is_set())
+    }
+}
+
+impl Default for Event {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,17 @@
+//! 任务间的阻塞同步原语，竞争时挂起当前任务而不是自旋。
+//!
+//! 只能在任务上下文中等待；`Event::set`、`Semaphore::release` 可在中断中调用。
+
+mod condvar;
+mod event;
+mod mutex;
+mod rwlock;
+mod semaphore;
+mod wait;
+
+pub use condvar::Condvar;
+pub use event::Event;
+pub use mutex::{Mutex, MutexGuard};
+pub use rwlock::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+pub use semaphore::Semaphore;
+pub(crate) use wait::WaitQueue;
@@ -0,0 +1,54 @@
+use core::{
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use super::WaitQueue;
+
+/// 计数信号量
+pub struct Semaphore {
+    count: AtomicUsize,
+    wq: WaitQueue,
+}
+
+impl Semaphore {
+    pub const fn new(count: usize) -> Self {
+        Self {
+            count: AtomicUsize::new(count),
+            wq: WaitQueue::new(),
+        }
+    }
+
+    pub fn acquire(&self) {
+        self.wq.wait_until(|| self.try_acquire());
+    }
+
+    /// 超时返回 `false`
+    pub fn acquire_timeout(&self, timeout: Duration) -> bool {
+        self.wq.wait_until_timeout(timeout, || self.try_acquire())
+    }
+
+    pub fn try_acquire(&self) -> bool {
+        let mut c = self.count.load(Ordering::Relaxed);
+        while c > 0 {
+            match self
+                .count
+                .compare_exchange_weak(c, c - 1, Ordering::Acquire, Ordering::Relaxed)
+            {
+                Ok(_) => return true,
+                Err(cur) => c = cur,
+            }
+        }
+        false
+    }
+
+    /// 可在中断上下文中调用
+    pub fn release(&self) {
+        self.count.fetch_add(1, Ordering::Release);
+        self.wq.notify_one();
+    }
+
+    pub fn available(&self) -> usize {
+        self.count.load(Ordering::Relaxed)
+    }
+}
@@ -0,0 +1,128 @@
+use core::time::Duration;
+
+use alloc::collections::vec_deque::VecDeque;
+
+use crate::{
+    irq::NoIrqGuard,
+    task::{self, Pid},
+    time,
+};
+
+/// 等待条件成立的任务队列
+pub(crate) struct WaitQueue {
+    waiters: spin::Mutex<VecDeque<Pid>>,
+}
+
+impl WaitQueue {
+    pub const fn new() -> Self {
+        Self {
+            waiters: spin::Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// 挂起当前任务直到 `cond` 返回 `true`
+    pub fn wait_until(&self, mut cond: impl FnMut() -> bool) {
+        let pid = task::current().pid;
+        while !self.prepare(pid, &mut cond) {
+            task::suspend();
+        }
+    }
+
+    /// 同 [`WaitQueue::wait_until`]，超时返回 `false`
+    pub fn wait_until_timeout(&self, timeout: Duration, mut cond: impl FnMut() -> bool) -> bool {
+        let pid = task::current().pid;
+        let deadline = time::since_boot() + timeout;
+        let mut timer = None;
+        let mut waited = false;
+
+        let ok = loop {
+            if time::since_boot() >= deadline {
+                let queued = self.remove(pid);
+                if cond() {
+                    break true;
+                }
+                // 超时前已被 notify_one 移出队列，把这次通知转给下一个等待者
+                if waited && !queued {
+                    self.notify_one();
+                }
+                break false;
+            }
+            if self.prepare(pid, &mut cond) {
+                break true;
+            }
+            waited = true;
+            if timer.is_none() {
+                let left = deadline.saturating_sub(time::since_boot());
+                timer = Some(time::after(left, move || {
+                    task::wake_up_in_irq(pid);
+                }));
+            }
+            task::suspend();
+        };
+        // 条件先成立时定时仍未触发，不取消会在之后误唤醒
+        if let Some(timer) = timer {
+            timer.cancel();
+        }
+        ok
+    }
+
+    /// 登记后再检查条件，避免唤醒发生在登记之前而丢失
+    fn prepare(&self, pid: Pid, cond: &mut impl FnMut() -> bool) -> bool {
+        if cond() {
+            self.remove(pid);
+            return true;
+        }
+        {
+            let _g = NoIrqGuard::new();
+            let mut waiters = self.waiters.lock();
+            if !waiters.contains(&pid) {
+                waiters.push_back(pid);
+            }
+        }
+        if cond() {
+            self.remove(pid);
+            return true;
+        }
+        false
+    }
+
+    /// 返回 `pid` 是否仍在队列中
+    fn remove(&self, pid: Pid) -> bool {
+        let _g = NoIrqGuard::new();
+        let mut waiters = self.waiters.lock();
+        let len = waiters.len();
+        waiters.retain(|p| *p != pid);
+        waiters.len() != len
+    }
+
+    /// 唤醒最早等待的任务，返回是否有任务被唤醒
+    pub fn notify_one(&self) -> bool {
+        let pid = {
+            let _g = NoIrqGuard::new();
+            self.waiters.lock().pop_front()
+        };
+        match pid {
+            Some(pid) => {
+                task::wake_up_in_irq(pid);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn notify_all(&self) {
+        let waiters = {
+            let _g = NoIrqGuard::new();
+            core::mem::take(&mut *self.waiters.lock())
+        };
+        for pid in waiters {
+            task::wake_up_in_irq(pid);
+        }
+    }
+}
+
+impl Default for WaitQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
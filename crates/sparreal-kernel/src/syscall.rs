@@ -0,0 +1,94 @@
+//! 用户任务的系统调用，调用号与 Linux 一致。
+//!
+//! 出错时返回负的错误码。
+
+use core::time::Duration;
+
+use alloc::string::String;
+
+use crate::{
+    hal_al::mmu::AccessSetting,
+    io::print::print,
+    irq,
+    task::{self, current},
+    time,
+};
+
+pub const SYS_WRITE: usize = 64;
+pub const SYS_EXIT: usize = 93;
+pub const SYS_NANOSLEEP: usize = 101;
+pub const SYS_SCHED_YIELD: usize = 124;
+pub const SYS_MMAP: usize = 222;
+
+pub const EBADF: isize = 9;
+pub const ENOMEM: isize = 12;
+pub const EFAULT: isize = 14;
+pub const EINVAL: isize = 22;
+pub const ENOSYS: isize = 38;
+
+const PROT_WRITE: usize = 2;
+const PROT_EXEC: usize = 4;
+const MAP_ANONYMOUS: usize = 0x20;
+
+/// 处理系统调用，在陷入内核的异常处理中调用。
+///
+/// 处理期间打开中断，返回前重新屏蔽。
+pub fn dispatch(num: usize, args: [usize; 6]) -> isize {
+    irq::enable_all();
+    let ret = match num {
+        SYS_WRITE => sys_write(args[0], args[1], args[2]),
+        SYS_EXIT => task::exit(args[0] as isize),
+        SYS_NANOSLEEP => sys_sleep(args[0]),
+        SYS_SCHED_YIELD => {
+            task::schedule();
+            0
+        }
+        SYS_MMAP => sys_mmap(args[1], args[2], args[3]),
+        _ => {
+            warn!("unknown syscall {num}");
+            -ENOSYS
+        }
+    };
+    irq::disable_all();
+    ret
+}
+
+fn sys_write(fd: usize, buf: usize, len: usize) -> isize {
+    if fd != 1 && fd != 2 {
+        return -EBADF;
+    }
+    let Some(aspace) = current().address_space.clone() else {
+        return -EFAULT;
+    };
+    if !aspace.is_mapped(buf, len) {
+        return -EFAULT;
+    }
+    let bytes = unsafe { core::slice::from_raw_parts(buf as *const u8, len) };
+    print(format_args!("{}", String::from_utf8_lossy(bytes)));
+    len as isize
+}
+
+/// 参数为纳秒数
+fn sys_sleep(nanos: usize) -> isize {
+    time::sleep(Duration::from_nanos(nanos as u64));
+    0
+}
+
+fn sys_mmap(len: usize, prot: usize, flags: usize) -> isize {
+    if len == 0 || flags & MAP_ANONYMOUS == 0 {
+        return -EINVAL;
+    }
+    let Some(aspace) = current().address_space.clone() else {
+        return -ENOMEM;
+    };
+    let access = match (prot & PROT_WRITE != 0, prot & PROT_EXEC != 0) {
+        (true, true) => AccessSetting::ReadWriteExecute,
+        (true, false) => AccessSetting::ReadWrite,
+        (false, true) => AccessSetting::ReadExecute,
+        (false, false) => AccessSetting::Read,
+    };
+    match aspace.mmap(len, access) {
+        Ok(va) => va.raw() as isize,
+        Err(_) => -ENOMEM,
+    }
+}
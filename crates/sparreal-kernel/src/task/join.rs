@@ -0,0 +1,84 @@
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use alloc::sync::Arc;
+use spin::Mutex;
+
+use super::{Pid, current, schedule::wake_up, suspend};
+
+pub(super) struct Packet<T> {
+    result: Mutex<Option<T>>,
+    finished: AtomicBool,
+    waiter: Mutex<Option<Pid>>,
+}
+
+impl<T> Packet<T> {
+    pub(super) fn new() -> Arc<Self> {
+        Arc::new(Self {
+            result: Mutex::new(None),
+            finished: AtomicBool::new(false),
+            waiter: Mutex::new(None),
+        })
+    }
+
+    /// 任务结束时保存返回值并唤醒等待者
+    pub(super) fn finish(&self, result: T) {
+        self.result.lock().replace(result);
+        self.done();
+    }
+
+    /// 任务被结束，没有返回值
+    pub(super) fn abort(&self) {
+        self.done();
+    }
+
+    fn done(&self) {
+        self.finished.store(true, Ordering::Release);
+        if let Some(pid) = self.waiter.lock().take() {
+            wake_up(pid);
+        }
+    }
+}
+
+/// 已创建任务的句柄，用于等待任务结束并取回返回值
+pub struct JoinHandle<T> {
+    pid: Pid,
+    packet: Arc<Packet<T>>,
+}
+
+impl<T> JoinHandle<T> {
+    pub(super) fn new(pid: Pid, packet: Arc<Packet<T>>) -> Self {
+        Self { pid, packet }
+    }
+
+    pub fn pid(&self) -> Pid {
+        self.pid
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.packet.finished.load(Ordering::Acquire)
+    }
+
+    /// 挂起当前任务直到目标任务结束，返回其返回值
+    ///
+    /// # Panics
+    ///
+    /// 目标任务被结束而没有返回值
+    pub fn join(self) -> T {
+        let pid = self.pid;
+        self.try_join()
+            .unwrap_or_else(|| panic!("task {pid:?} was killed"))
+    }
+
+    /// 同 [`join`](Self::join)，目标任务被结束时返回 `None`
+    pub fn try_join(self) -> Option<T> {
+        while !self.is_finished() {
+            self.packet.waiter.lock().replace(current().pid);
+            // 登记后再检查，避免错过唤醒
+            if self.is_finished() {
+                break;
+            }
+            suspend();
+        }
+        self.packet.result.lock().take()
+    }
+}
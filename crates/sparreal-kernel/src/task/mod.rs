@@ -1,22 +1,41 @@
-use alloc::string::{String, ToString};
+use alloc::{
+    boxed::Box,
+    string::{String, ToString},
+    sync::Arc,
+};
 use tcb::set_current;
 
+use crate::{
+    mem::mmu::{AddressSpace, aspace},
+    platform,
+};
+
+mod join;
 mod schedule;
 mod tcb;
+mod user;
 
-pub use schedule::suspend;
-pub use tcb::{Pid, TaskControlBlock, TaskControlBlockData, current};
+pub use join::JoinHandle;
+pub use schedule::{PRIORITY_LEVELS, TIME_SLICE, schedule, suspend, wake_up, wake_up_in_irq};
+pub(crate) use schedule::{RunQueue, schedule_if_needed, set_need_resched};
+pub use tcb::{Pid, TaskControlBlock, TaskControlBlockData, current, try_current};
+pub use user::{USER_STACK_SIZE, USER_STACK_TOP, UserEntry, load_elf, spawn_user};
 
 #[derive(Debug, Clone)]
 pub enum TaskError {
     NoMemory,
+    /// 用户程序镜像无效
+    InvalidImage(&'static str),
 }
 
 #[derive(Debug, Clone)]
 pub struct TaskConfig {
     pub name: String,
+    /// 优先级，数值越大越优先，取值范围 `0..PRIORITY_LEVELS`
     pub priority: usize,
     pub stack_size: usize,
+    /// 任务运行的用户地址空间，`None` 时只使用内核地址空间
+    pub address_space: Option<Arc<AddressSpace>>,
 }
 
 impl TaskConfig {
@@ -25,24 +44,115 @@ impl TaskConfig {
             name: name.to_string(),
             priority: 0,
             stack_size: 2 * 1024 * 1024,
+            address_space: None,
         }
     }
 }
 
-pub fn spawn_with_config<F>(f: F, config: TaskConfig) -> Result<(), TaskError>
+pub fn spawn_with_config<F, T>(f: F, config: TaskConfig) -> Result<JoinHandle<T>, TaskError>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    spawn_packet(
+        move |packet| {
+            let aborted = packet.clone();
+            let mut task = current();
+            task.exit_hook = Some(Box::new(move |_| aborted.abort()));
+            packet.finish(f())
+        },
+        config,
+    )
+}
+
+/// 创建任务，`f` 负责在任务结束时填充 `packet`
+fn spawn_packet<F, T>(f: F, config: TaskConfig) -> Result<JoinHandle<T>, TaskError>
 where
-    F: FnOnce() + Send + 'static,
+    F: FnOnce(Arc<join::Packet<T>>) + Send + 'static,
+    T: Send + 'static,
 {
-    let task = TaskControlBlock::new(f, config)?;
+    let packet = join::Packet::new();
+    let mut task = TaskControlBlock::new(
+        {
+            let packet = packet.clone();
+            move || f(packet)
+        },
+        config,
+    )?;
+    let cpu = schedule::select_cpu();
+    task.cpu = cpu;
+    let pid = task.pid;
+    let priority = task.priority;
+    // 入队后任务可能已在其他CPU上运行结束，不能再访问 task
+    schedule::ready_push(task);
 
-    tcb::current().switch_to(&task);
+    // 新任务在当前CPU上且优先级不低于当前任务时立即让出
+    if cpu == platform::cpu_current_id() && priority >= current().priority {
+        schedule();
+    }
+
+    Ok(JoinHandle::new(pid, packet))
+}
+
+/// 结束当前任务，不会返回。
+///
+/// 用户任务的 [`JoinHandle`] 以 `code` 完成；内核任务没有返回值，
+/// [`JoinHandle::try_join`] 返回 `None`。
+pub fn exit(code: isize) -> ! {
+    let mut task = current();
+    if let Some(hook) = task.exit_hook.take() {
+        hook(code);
+    }
+    schedule::task_exited(task.pid);
+    task.state = tcb::TaskState::Stopped;
+    schedule();
+    unreachable!("task exited!");
+}
 
-    Ok(())
+/// 当前任务因异常无法继续执行时结束它，退出码为 `-1`。
+///
+/// # Panics
+///
+/// 当前任务是主任务或空闲任务
+pub fn kill_current() -> ! {
+    let task = current();
+    // new_main 创建的任务使用启动栈，没有自己的栈
+    if task.stack.is_none() || schedule::is_idle(&task) {
+        panic!("can not kill task {:?} ({:?})", task.name, task.pid);
+    }
+    exit(-1)
 }
 
 pub fn init() {
-    let task = TaskControlBlock::new_main();
+    let task = TaskControlBlock::new_main("Main");
+    set_current(&task);
+    aspace::activate(None);
+
+    let idle = TaskControlBlock::new(
+        idle_loop,
+        TaskConfig {
+            name: "Idle".into(),
+            priority: 0,
+            stack_size: IDLE_STACK_SIZE,
+            address_space: None,
+        },
+    )
+    .expect("idle task no memory");
+    schedule::init_current_cpu(idle);
+}
+
+/// Create the idle task of a secondary CPU and make it current.
+pub(crate) fn init_current_cpu() {
+    let task = TaskControlBlock::new_main("Idle");
     set_current(&task);
+    aspace::activate(None);
+    schedule::init_current_cpu(task);
 }
 
-pub fn wake_up_in_irq(_pid: Pid) {}
+const IDLE_STACK_SIZE: usize = 0x4000;
+
+fn idle_loop() {
+    loop {
+        platform::wait_for_interrupt();
+    }
+}
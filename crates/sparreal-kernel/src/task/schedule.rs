@@ -1,50 +1,265 @@
-use alloc::collections::vec_deque::VecDeque;
-use log::debug;
+use core::{
+    sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use alloc::collections::{btree_map::BTreeMap, vec_deque::VecDeque};
 use spin::Mutex;
 
-use crate::platform;
+use crate::{
+    globals::{cpu_global, cpu_global_by_id},
+    irq::{
+        NoIrqGuard,
+        ipi::{self, IpiKind},
+    },
+    platform::{self, CPUId},
+    time,
+};
+
+use super::tcb::{Pid, TaskControlBlock, TaskState, current};
+
+/// 优先级级数，`TaskConfig::priority` 越大越优先，超出部分按最高级处理
+pub const PRIORITY_LEVELS: usize = 32;
+/// 同优先级任务轮转的时间片
+pub const TIME_SLICE: Duration = Duration::from_millis(10);
+
+static WAITING: Mutex<WaitQueue> = Mutex::new(WaitQueue::new());
 
-use super::tcb::{TaskControlBlock, TaskState, current};
+/// 挂起的任务，按 Pid 唤醒
+struct WaitQueue {
+    tasks: BTreeMap<Pid, TaskControlBlock>,
+    /// 尚未结束的任务，唤醒时未挂起的任务在其 TCB 上记录
+    live: BTreeMap<Pid, TaskControlBlock>,
+}
 
-static IDLE: Mutex<VecDeque<TaskControlBlock>> = Mutex::new(VecDeque::new());
-static FINISHED: Mutex<VecDeque<TaskControlBlock>> = Mutex::new(VecDeque::new());
+impl WaitQueue {
+    const fn new() -> Self {
+        Self {
+            tasks: BTreeMap::new(),
+            live: BTreeMap::new(),
+        }
+    }
+}
+
+/// 每个CPU的就绪队列
+#[derive(Default)]
+pub struct RunQueue {
+    ready: Mutex<[VecDeque<TaskControlBlock>; PRIORITY_LEVELS]>,
+    len: AtomicUsize,
+    /// 没有就绪任务时运行，不进入就绪队列
+    idle: AtomicPtr<u8>,
+    need_resched: AtomicBool,
+    /// 已结束的任务，切换完成后才能释放其栈，由本CPU回收
+    finished: Mutex<VecDeque<TaskControlBlock>>,
+}
+
+impl RunQueue {
+    fn push(&self, tcb: TaskControlBlock) {
+        let _g = NoIrqGuard::new();
+        self.ready.lock()[level(tcb.priority)].push_back(tcb);
+        self.len.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// 取出优先级不低于 `min_priority` 的最高优先级任务
+    fn pop(&self, min_priority: usize) -> Option<TaskControlBlock> {
+        let _g = NoIrqGuard::new();
+        let mut ready = self.ready.lock();
+        for queue in ready[level(min_priority)..].iter_mut().rev() {
+            while let Some(one) = queue.pop_front() {
+                self.len.fetch_sub(1, Ordering::Relaxed);
+                if matches!(one.state, TaskState::Stopped) {
+                    unsafe { one.drop() };
+                    continue;
+                }
+                return Some(one);
+            }
+        }
+        None
+    }
+
+    /// 就绪任务数量
+    fn len(&self) -> usize {
+        self.len.load(Ordering::Relaxed)
+    }
+
+    /// 释放已切走的结束任务
+    fn reclaim(&self) {
+        let _g = NoIrqGuard::new();
+        let finished = core::mem::take(&mut *self.finished.lock());
+        for tcb in finished {
+            unsafe { tcb.drop() };
+        }
+    }
+
+    fn idle_task(&self) -> TaskControlBlock {
+        let ptr = self.idle.load(Ordering::Acquire);
+        assert!(!ptr.is_null(), "idle task not init");
+        TaskControlBlock::from(ptr)
+    }
+
+    fn is_idle(&self, tcb: &TaskControlBlock) -> bool {
+        self.idle.load(Ordering::Acquire) == tcb.addr()
+    }
+}
+
+fn level(priority: usize) -> usize {
+    priority.min(PRIORITY_LEVELS - 1)
+}
+
+fn run_queue() -> &'static RunQueue {
+    &cpu_global().run_queue
+}
 
+/// 设置当前CPU的空闲任务，并启动时间片时钟
+pub(super) fn init_current_cpu(idle: TaskControlBlock) {
+    run_queue().idle.store(idle.addr(), Ordering::Release);
+    time::every(TIME_SLICE, || {
+        run_queue().need_resched.store(true, Ordering::Relaxed);
+    });
+}
+
+/// `tcb` 是否为当前CPU的空闲任务
+pub(super) fn is_idle(tcb: &TaskControlBlock) -> bool {
+    run_queue().is_idle(tcb)
+}
+
+/// 选择就绪任务最少的在线CPU
+pub(super) fn select_cpu() -> CPUId {
+    platform::cpu_online_list()
+        .into_iter()
+        .filter_map(|id| cpu_global_by_id(id).map(|c| (id, c.run_queue.len())))
+        .min_by_key(|(_, len)| *len)
+        .map(|(id, _)| id)
+        .unwrap_or_else(platform::cpu_current_id)
+}
+
+/// 切换到下一个就绪任务。
+///
+/// 当前任务仍可运行时，只会被同级或更高优先级的任务抢占；
+/// 没有可运行的任务时切换到空闲任务。
 pub fn schedule() {
-    let idle = idle_pop();
-    if let Some(mut idle) = idle {
-        let mut cu = current();
-        if matches!(cu.state, TaskState::Running) {
-            cu.state = TaskState::Suspend;
+    let _g = NoIrqGuard::new();
+    let rq = run_queue();
+    rq.need_resched.store(false, Ordering::Relaxed);
+    rq.reclaim();
+
+    let mut cu = current();
+    // 放入等待队列后其他CPU可随时唤醒它并修改 `cu.state`，之后只使用此快照
+    let mut state = cu.state;
+    if matches!(state, TaskState::Suspend) {
+        let mut waiting = WAITING.lock();
+        if cu.wake_pending.swap(false, Ordering::AcqRel) {
+            cu.state = TaskState::Running;
+            state = TaskState::Running;
+        } else {
+            waiting.tasks.insert(cu.pid, cu);
         }
-        idle.state = TaskState::Running;
+    }
+    let runnable = matches!(state, TaskState::Running);
 
-        cu.switch_to(&idle);
+    let next = if runnable && !rq.is_idle(&cu) {
+        rq.pop(cu.priority)
     } else {
-        debug!("No task idle");
-        loop {
-            platform::wait_for_interrupt();
-        }
+        rq.pop(0)
+    };
+
+    let mut next = match next {
+        Some(next) => next,
+        None if runnable => return,
+        None => rq.idle_task(),
+    };
+
+    // 挂起后在切换前已被唤醒
+    if next.addr() == cu.addr() {
+        cu.state = TaskState::Running;
+        return;
+    }
+
+    if runnable {
+        cu.state = TaskState::Idle;
+        state = TaskState::Idle;
+    }
+    next.state = TaskState::Running;
+
+    cu.switch_to(&next, state);
+}
+
+/// 中断返回前调用，时间片用完时重新调度
+pub(crate) fn schedule_if_needed() {
+    if run_queue().need_resched.load(Ordering::Relaxed) {
+        schedule();
     }
 }
 
-pub fn idle_push(tcb: TaskControlBlock) {
-    IDLE.lock().push_back(tcb);
+/// 当前CPU从中断返回时重新调度
+pub(crate) fn set_need_resched() {
+    run_queue().need_resched.store(true, Ordering::Relaxed);
+}
+
+/// 放入任务所属CPU的就绪队列，空闲任务除外。
+///
+/// 所属CPU不是当前CPU时发送 IPI，使其从空闲等待中醒来并重新调度。
+pub(super) fn ready_push(tcb: TaskControlBlock) {
+    let rq = match cpu_global_by_id(tcb.cpu) {
+        Some(cpu) => &cpu.run_queue,
+        None => run_queue(),
+    };
+    if rq.is_idle(&tcb) {
+        return;
+    }
+    let cpu = tcb.cpu;
+    rq.push(tcb);
+    if cpu != platform::cpu_current_id() {
+        // 目标CPU尚未上线时会在上线后调度
+        let _ = ipi::send(cpu, IpiKind::Reschedule);
+    }
 }
 
-pub fn idle_pop() -> Option<TaskControlBlock> {
-    let mut g = IDLE.lock();
-    while let Some(one) = g.pop_front() {
-        if matches!(one.state, TaskState::Stopped) {
-            unsafe { one.drop() };
-            continue;
+/// 唤醒挂起的任务，可在中断上下文中调用。
+///
+/// 任务尚未挂起时记录唤醒，其下一次挂起会立即返回，因此等待方需要重新检查条件。
+/// 不存在或已结束的任务忽略。返回任务是否从等待队列中移出。
+pub fn wake_up_in_irq(pid: Pid) -> bool {
+    let _g = NoIrqGuard::new();
+    let mut waiting = WAITING.lock();
+    let Some(mut tcb) = waiting.tasks.remove(&pid) else {
+        if let Some(tcb) = waiting.live.get(&pid) {
+            tcb.wake_pending.store(true, Ordering::Release);
         }
-        return Some(one);
+        return false;
+    };
+    drop(waiting);
+
+    tcb.state = TaskState::Idle;
+    if let Some(cpu) = cpu_global_by_id(tcb.cpu) {
+        cpu.run_queue.need_resched.store(true, Ordering::Relaxed);
     }
-    None
+    ready_push(tcb);
+    true
+}
+
+/// 在任务上下文中唤醒任务，被唤醒的任务优先级不低于当前任务时立即让出
+pub fn wake_up(pid: Pid) -> bool {
+    let woken = wake_up_in_irq(pid);
+    schedule_if_needed();
+    woken
+}
+
+/// 登记新建的任务，此后才能记录对它的唤醒
+pub(super) fn task_created(tcb: TaskControlBlock) {
+    let _g = NoIrqGuard::new();
+    WAITING.lock().live.insert(tcb.pid, tcb);
+}
+
+/// 任务结束，之后对它的唤醒被忽略
+pub(super) fn task_exited(pid: Pid) {
+    let _g = NoIrqGuard::new();
+    WAITING.lock().live.remove(&pid);
 }
 
 pub fn finished_push(tcb: TaskControlBlock) {
-    FINISHED.lock().push_back(tcb);
+    let _g = NoIrqGuard::new();
+    run_queue().finished.lock().push_back(tcb);
 }
 
 pub fn suspend() {
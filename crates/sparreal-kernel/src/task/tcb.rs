@@ -1,17 +1,24 @@
 use core::{
-    alloc::Layout,
     fmt::Debug,
     ops::{Deref, DerefMut},
-    ptr::NonNull,
-    sync::atomic::{AtomicUsize, Ordering},
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
 };
 
-use alloc::{boxed::Box, string::String};
+use alloc::{boxed::Box, string::String, sync::Arc};
 use log::trace;
 
-use crate::{platform, task::schedule::*};
+use crate::{
+    globals::cpu_global,
+    irq,
+    mem::{
+        mmu::{AddressSpace, aspace},
+        stack::KernelStack,
+    },
+    platform::{self, CPUId},
+    task::schedule::*,
+};
 
-use super::{TaskConfig, TaskError};
+use super::{TaskConfig, TaskError, user::UserEntry};
 
 #[repr(transparent)]
 #[derive(Clone, Copy)]
@@ -44,7 +51,7 @@ impl Default for Pid {
 
 impl Debug for Pid {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
-        writeln!(f, "{:?}", self.0)
+        write!(f, "{:?}", self.0)
     }
 }
 
@@ -53,30 +60,27 @@ impl TaskControlBlock {
     where
         F: FnOnce() + Send + 'static,
     {
-        let entry_box = Box::new(entry);
-
-        let buffer = NonNull::new(unsafe {
-            alloc::alloc::alloc_zeroed(
-                Layout::from_size_align(Self::tcb_size(config.stack_size), platform::page_size())
-                    .unwrap(),
-            )
-        })
-        .ok_or(TaskError::NoMemory)?;
-
-        let pid = Pid::new();
-
-        unsafe {
-            let task_data = &mut *(buffer.as_ptr() as *mut TaskControlBlockData);
-            task_data.pid = pid;
-            task_data.stack_size = config.stack_size;
-            task_data.priority = config.priority;
-            task_data.name = config.name;
-            task_data.state = TaskState::Idle;
-            task_data.entry = Some(entry_box);
-        }
-
-        let mut task = Self(buffer.as_ptr());
-        task.sp = task.stack_top() as usize;
+        let stack = KernelStack::new(config.stack_size).map_err(|_| TaskError::NoMemory)?;
+
+        let data = Box::new(TaskControlBlockData {
+            pid: Pid::new(),
+            name: config.name,
+            priority: config.priority,
+            cpu: platform::cpu_current_id(),
+            stack_size: config.stack_size,
+            entry: Some(Box::new(entry)),
+            state: TaskState::Idle,
+            sp: 0,
+            kstack_top: stack.top(),
+            stack: Some(stack),
+            address_space: config.address_space,
+            user_entry: None,
+            exit_hook: None,
+            wake_pending: AtomicBool::new(false),
+        });
+
+        let mut task = Self(Box::into_raw(data) as *mut u8);
+        task.sp = task.kstack_top;
 
         unsafe {
             task.sp -= platform::cpu_context_size();
@@ -85,67 +89,57 @@ impl TaskControlBlock {
             platform::cpu_context_set_sp(ctx_ptr, task.sp);
             platform::cpu_context_set_pc(ctx_ptr, task_entry as _);
         }
+        task_created(task);
         Ok(task)
     }
 
-    pub(super) fn new_main() -> Self {
-        let entry_box = Box::new(|| {});
-
-        let buffer = NonNull::new(unsafe {
-            alloc::alloc::alloc_zeroed(
-                Layout::from_size_align(Self::tcb_size(0), platform::page_size()).unwrap(),
-            )
-        })
-        .ok_or(TaskError::NoMemory)
-        .expect("main task no memory");
-
-        let pid = Pid::new();
-
-        unsafe {
-            let task_data = &mut *(buffer.as_ptr() as *mut TaskControlBlockData);
-            task_data.pid = pid;
-            task_data.stack_size = 0;
-            task_data.priority = 0;
-            task_data.name = "Main".into();
-            task_data.state = TaskState::Running;
-            task_data.entry = Some(entry_box);
-        }
-        Self(buffer.as_ptr())
-    }
-
-    fn tcb_size(stack_size: usize) -> usize {
-        size_of::<TaskControlBlockData>() + stack_size
-    }
-
-    fn stack_bottom(&self) -> *mut u8 {
-        unsafe { self.0.add(size_of::<TaskControlBlockData>()) }
+    /// 运行在当前CPU启动栈上的任务
+    pub(super) fn new_main(name: &str) -> Self {
+        let data = Box::new(TaskControlBlockData {
+            pid: Pid::new(),
+            name: name.into(),
+            priority: 0,
+            cpu: platform::cpu_current_id(),
+            stack_size: 0,
+            entry: Some(Box::new(|| {})),
+            state: TaskState::Running,
+            sp: 0,
+            kstack_top: cpu_global().stack.end.raw(),
+            stack: None,
+            address_space: None,
+            user_entry: None,
+            exit_hook: None,
+            wake_pending: AtomicBool::new(false),
+        });
+        let task = Self(Box::into_raw(data) as *mut u8);
+        task_created(task);
+        task
     }
 
-    fn stack_top(&self) -> *mut u8 {
-        unsafe { self.stack_bottom().add(self.stack_size) }
+    pub(super) fn stack_top(&self) -> *mut u8 {
+        self.kstack_top as *mut u8
     }
 
     pub(super) unsafe fn drop(self) {
-        let size = Self::tcb_size(self.stack_size);
-
-        unsafe {
-            alloc::alloc::dealloc(
-                self.0,
-                Layout::from_size_align_unchecked(size, platform::page_size()),
-            )
-        };
+        unsafe { drop(Box::from_raw(self.0 as *mut TaskControlBlockData)) };
     }
 
-    fn addr(&self) -> *mut u8 {
+    pub(super) fn addr(&self) -> *mut u8 {
         self.0 as _
     }
 
-    pub(super) fn switch_to(&self, next: &TaskControlBlock) {
+    /// `state` 为调度时取得的当前任务状态，挂起的任务此时可能已被其他CPU唤醒
+    pub(super) fn switch_to(&self, next: &TaskControlBlock, state: TaskState) {
         trace!("switch {} -> {}", self.name, next.name);
         set_current(next);
-        match self.state {
+        if !same_address_space(self, next) {
+            aspace::activate(next.address_space.as_deref());
+        }
+        match state {
             TaskState::Stopped => finished_push(*self),
-            _ => idle_push(*self),
+            // 已在 schedule 中放入等待队列
+            TaskState::Suspend => {}
+            _ => ready_push(*self),
         }
 
         unsafe {
@@ -172,10 +166,31 @@ pub struct TaskControlBlockData {
     pub pid: Pid,
     pub name: String,
     pub priority: usize,
+    /// 所属CPU，就绪时进入该CPU的就绪队列
+    pub cpu: CPUId,
     pub stack_size: usize,
+    /// 内核栈栈顶，栈溢出时异常处理在此继续
+    pub kstack_top: usize,
+    /// 主任务与从CPU的空闲任务使用启动栈，为 `None`
+    pub stack: Option<KernelStack>,
     pub entry: Option<Box<dyn FnOnce()>>,
     pub state: TaskState,
     pub sp: usize,
+    pub address_space: Option<Arc<AddressSpace>>,
+    /// `entry` 返回后进入用户态
+    pub user_entry: Option<UserEntry>,
+    /// 通过 [`exit`](super::exit) 结束时以退出码调用
+    pub exit_hook: Option<Box<dyn FnOnce(isize)>>,
+    /// 未挂起时被唤醒，下一次挂起立即返回
+    pub(super) wake_pending: AtomicBool,
+}
+
+fn same_address_space(a: &TaskControlBlock, b: &TaskControlBlock) -> bool {
+    match (&a.address_space, &b.address_space) {
+        (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+        (None, None) => true,
+        _ => false,
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
@@ -188,9 +203,15 @@ pub enum TaskState {
 
 extern "C" fn task_entry() -> ! {
     let mut task = current();
+    // 首次切换可能发生在中断上下文中，此时中断仍处于屏蔽状态
+    irq::enable_all();
 
     if let Some(entry) = task.entry.take() {
         entry();
+        if let Some(user) = task.user_entry.take() {
+            unsafe { platform::user_enter(user.pc, user.sp, task.stack_top() as usize, user.arg) }
+        }
+        task_exited(task.pid);
         task.state = TaskState::Stopped;
     }
     schedule();
@@ -202,6 +223,12 @@ pub fn current() -> TaskControlBlock {
     TaskControlBlock::from(ptr)
 }
 
+/// 尚未初始化任务时返回 `None`
+pub fn try_current() -> Option<TaskControlBlock> {
+    let ptr = unsafe { platform::get_current_tcb_addr() };
+    (!ptr.is_null()).then(|| TaskControlBlock::from(ptr))
+}
+
 pub fn set_current(tcb: &TaskControlBlock) {
     unsafe { platform::set_current_tcb_addr(tcb.addr()) };
 }
@@ -0,0 +1,158 @@
+use alloc::{boxed::Box, sync::Arc};
+
+use crate::{
+    hal_al::mmu::{AccessSetting, PagingError},
+    mem::{
+        align_down, align_up,
+        mmu::{AddressSpace, USER_SPACE_END},
+        page_size,
+    },
+    platform::{self, CacheOp},
+};
+
+use super::{JoinHandle, TaskConfig, TaskError, current, spawn_packet};
+
+/// 用户栈栈顶
+pub const USER_STACK_TOP: usize = 0x7fff_0000_0000;
+/// 用户栈大小
+pub const USER_STACK_SIZE: usize = 0x10000;
+
+/// 用户态入口
+#[derive(Debug, Clone, Copy)]
+pub struct UserEntry {
+    pub pc: usize,
+    pub sp: usize,
+    /// 进入用户态时的第一个参数
+    pub arg: usize,
+}
+
+const PT_LOAD: u32 = 1;
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+
+/// 将 ELF64 小端镜像的 `PT_LOAD` 段载入 `aspace`，并分配用户栈。
+///
+/// 各段按页对齐映射，不能共享同一页。
+pub fn load_elf(aspace: &AddressSpace, image: &[u8]) -> Result<UserEntry, TaskError> {
+    if image.len() < 0x40 || image[..4] != *b"\x7fELF" {
+        return Err(TaskError::InvalidImage("not an ELF file"));
+    }
+    if image[4] != 2 || image[5] != 1 {
+        return Err(TaskError::InvalidImage("not a little-endian ELF64 file"));
+    }
+
+    let entry = read_u64(image, 0x18)? as usize;
+    let phoff = read_u64(image, 0x20)? as usize;
+    let phentsize = read_u16(image, 0x36)? as usize;
+    let phnum = read_u16(image, 0x38)? as usize;
+
+    for i in 0..phnum {
+        let ph = phoff
+            .checked_add(i * phentsize)
+            .filter(|&ph| ph < image.len())
+            .ok_or(TaskError::InvalidImage("truncated ELF file"))?;
+        if read_u32(image, ph)? != PT_LOAD {
+            continue;
+        }
+        let flags = read_u32(image, ph + 4)?;
+        let offset = read_u64(image, ph + 0x08)? as usize;
+        let vaddr = read_u64(image, ph + 0x10)? as usize;
+        let filesz = read_u64(image, ph + 0x20)? as usize;
+        let memsz = read_u64(image, ph + 0x28)? as usize;
+
+        if filesz > memsz {
+            return Err(TaskError::InvalidImage(
+                "segment file size exceeds memory size",
+            ));
+        }
+        let data = offset
+            .checked_add(filesz)
+            .and_then(|end| image.get(offset..end))
+            .ok_or(TaskError::InvalidImage("segment out of file"))?;
+        let end = vaddr
+            .checked_add(memsz)
+            .filter(|&end| end <= USER_SPACE_END)
+            .ok_or(TaskError::InvalidImage("segment out of user space"))?;
+
+        let start = align_down(vaddr, page_size());
+        let size = align_up(end, page_size()) - start;
+        let access = match (flags & PF_W != 0, flags & PF_X != 0) {
+            (true, true) => AccessSetting::ReadWriteExecute,
+            (true, false) => AccessSetting::ReadWrite,
+            (false, true) => AccessSetting::ReadExecute,
+            (false, false) => AccessSetting::Read,
+        };
+
+        let kva = aspace
+            .map_alloc(start.into(), size, access)
+            .map_err(map_err)?;
+        unsafe {
+            let dst = kva.as_ptr().add(vaddr - start);
+            dst.copy_from_nonoverlapping(data.as_ptr(), data.len());
+        }
+        // 代码经数据缓存写入，取指前需写回
+        platform::dcache_range(CacheOp::Clean, kva.as_ptr() as usize, size);
+    }
+
+    aspace
+        .map_alloc(
+            (USER_STACK_TOP - USER_STACK_SIZE).into(),
+            USER_STACK_SIZE,
+            AccessSetting::ReadWrite,
+        )
+        .map_err(map_err)?;
+
+    Ok(UserEntry {
+        pc: entry,
+        sp: USER_STACK_TOP,
+        arg: 0,
+    })
+}
+
+/// 在新地址空间中载入 ELF 镜像并创建用户任务，`JoinHandle` 返回退出码。
+///
+/// `config.address_space` 为 `None` 时新建地址空间，`config.stack_size` 为内核栈大小。
+pub fn spawn_user(image: &[u8], mut config: TaskConfig) -> Result<JoinHandle<isize>, TaskError> {
+    let aspace = match config.address_space.take() {
+        Some(aspace) => aspace,
+        None => Arc::new(AddressSpace::new().map_err(map_err)?),
+    };
+    let entry = load_elf(&aspace, image)?;
+    config.address_space = Some(aspace);
+
+    spawn_packet(
+        move |packet| {
+            let mut task = current();
+            task.exit_hook = Some(Box::new(move |code| packet.finish(code)));
+            task.user_entry = Some(entry);
+        },
+        config,
+    )
+}
+
+fn map_err(e: PagingError) -> TaskError {
+    match e {
+        PagingError::NoMemory => TaskError::NoMemory,
+        _ => TaskError::InvalidImage("segments overlap"),
+    }
+}
+
+fn read_bytes<const N: usize>(image: &[u8], offset: usize) -> Result<[u8; N], TaskError> {
+    offset
+        .checked_add(N)
+        .and_then(|end| image.get(offset..end))
+        .and_then(|b| b.try_into().ok())
+        .ok_or(TaskError::InvalidImage("truncated ELF file"))
+}
+
+fn read_u16(image: &[u8], offset: usize) -> Result<u16, TaskError> {
+    read_bytes(image, offset).map(u16::from_le_bytes)
+}
+
+fn read_u32(image: &[u8], offset: usize) -> Result<u32, TaskError> {
+    read_bytes(image, offset).map(u32::from_le_bytes)
+}
+
+fn read_u64(image: &[u8], offset: usize) -> Result<u64, TaskError> {
+    read_bytes(image, offset).map(u64::from_le_bytes)
+}
@@ -6,9 +6,14 @@ use core::{
 
 use crate::{
     globals::{cpu_global, cpu_global_meybeuninit, cpu_global_mut},
-    irq::{IrqHandleResult, IrqParam},
+    irq::{
+        IrqHandleResult, IrqParam, NoIrqGuard,
+        ipi::{self, IpiError},
+    },
+    platform::CPUId,
 };
 
+pub use queue::TimerHandle;
 use rdrive::IrqId;
 use spin::{Mutex, MutexGuard};
 pub use timer::Timer;
@@ -25,16 +30,17 @@ pub(crate) struct TimerData {
 unsafe impl Sync for TimerData {}
 unsafe impl Send for TimerData {}
 
+/// 持有期间屏蔽中断：被抢占或在中断中再次加锁都会在自旋锁上死锁
 pub(crate) struct Guard<'a> {
     _guard: MutexGuard<'a, ()>,
+    _irq: NoIrqGuard,
     timer: *mut Option<Timer>,
-    irq_state: bool,
 }
 
 impl Drop for Guard<'_> {
     fn drop(&mut self) {
         if let Some(t) = unsafe { &mut *self.timer } {
-            t.set_irq_enable(self.irq_state);
+            t.set_irq_enable(t.has_pending());
         }
     }
 }
@@ -55,17 +61,12 @@ impl DerefMut for Guard<'_> {
 
 impl TimerData {
     pub fn lock<'a>(&'a self) -> Guard<'a> {
-        let timer = unsafe { &mut *self.timer.get() };
-        let mut irq_state = false;
-        if let Some(t) = timer {
-            irq_state = t.get_irq_status();
-            t.set_irq_enable(false);
-        }
+        let irq = NoIrqGuard::new();
         let g = self.mutex.lock();
         Guard {
             _guard: g,
-            timer: timer as _,
-            irq_state,
+            _irq: irq,
+            timer: self.timer.get(),
         }
     }
 
@@ -118,13 +119,50 @@ fn timer_data() -> &'static TimerData {
     &cpu_global().timer
 }
 
-pub fn after(duration: Duration, call: impl Fn() + 'static) {
+/// 定时器尚未初始化时返回已取消的句柄
+pub fn after(duration: Duration, call: impl Fn() + 'static) -> TimerHandle {
+    let mut g = timer_data().lock();
+    match g.as_mut() {
+        Some(t) => t.after(duration, call),
+        None => cancelled(),
+    }
+}
+
+/// 在 `cpu` 的定时器上设置单次定时，回调在该CPU上执行。
+///
+/// 由目标CPU在 IPI 中加入其定时器队列；定时器锁持有期间屏蔽中断，不会在中断中重入。
+pub fn after_on(
+    cpu: CPUId,
+    duration: Duration,
+    call: impl Fn() + Send + 'static,
+) -> Result<TimerHandle, IpiError> {
+    let handle = TimerHandle::default();
+    let remote = handle.clone();
+    ipi::call_on(
+        cpu,
+        move || match timer_data().lock().as_mut() {
+            Some(t) => t.after_with(duration, call, remote),
+            None => remote.cancel(),
+        },
+        false,
+    )?;
+    Ok(handle)
+}
+
+pub fn every(duration: Duration, call: impl Fn() + 'static) -> TimerHandle {
     let mut g = timer_data().lock();
-    if let Some(t) = g.as_mut() {
-        t.after(duration, call);
+    match g.as_mut() {
+        Some(t) => t.every(duration, call),
+        None => cancelled(),
     }
 }
 
+fn cancelled() -> TimerHandle {
+    let handle = TimerHandle::default();
+    handle.cancel();
+    handle
+}
+
 pub fn spin_delay(duration: Duration) {
     let now = since_boot();
     let at = now + duration;
@@ -136,10 +174,22 @@ pub fn spin_delay(duration: Duration) {
     }
 }
 
+/// 挂起当前任务直到 `duration` 之后
 pub fn sleep(duration: Duration) {
     let pid = crate::task::current().pid;
-    after(duration, move || {
-        crate::task::wake_up_in_irq(pid);
-    });
-    crate::task::suspend();
+    let deadline = since_boot() + duration;
+
+    // 可能被其他唤醒提前唤醒，未到期则重新挂起
+    loop {
+        let now = since_boot();
+        if now >= deadline {
+            break;
+        }
+        let timer = after(deadline - now, move || {
+            crate::task::wake_up_in_irq(pid);
+        });
+        crate::task::suspend();
+        // 提前唤醒时不再需要，避免之后误唤醒
+        timer.cancel();
+    }
 }
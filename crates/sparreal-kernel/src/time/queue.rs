@@ -1,5 +1,8 @@
-use alloc::{boxed::Box, vec::Vec};
-use core::fmt::Debug;
+use alloc::{sync::Arc, vec::Vec};
+use core::{
+    fmt::Debug,
+    sync::atomic::{AtomicBool, Ordering},
+};
 
 #[derive(Debug)]
 pub struct Queue {
@@ -10,7 +13,30 @@ pub struct Event {
     pub at_tick: u64,
     pub interval: Option<u64>,
     pub called: bool,
-    pub callback: Box<dyn Fn()>,
+    pub callback: Arc<dyn Fn()>,
+    pub handle: TimerHandle,
+}
+
+impl Event {
+    fn is_done(&self) -> bool {
+        self.called || self.handle.is_cancelled()
+    }
+}
+
+/// [`after`](super::after) 与 [`every`](super::every) 返回，用于取消尚未触发的定时，
+/// 可在任意CPU与中断上下文中调用
+#[derive(Debug, Clone, Default)]
+pub struct TimerHandle(Arc<AtomicBool>);
+
+impl TimerHandle {
+    /// 取消后回调不再执行，正在执行的回调不受影响
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Release);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Acquire)
+    }
 }
 
 impl Debug for Event {
@@ -29,26 +55,25 @@ impl Queue {
     }
 
     pub fn add_and_next_tick(&mut self, event: Event) -> u64 {
-        self.events.retain(|e| !e.called);
+        self.events.retain(|e| !e.is_done());
         self.events.push(event);
         self.events.sort_by(|a, b| a.at_tick.cmp(&b.at_tick));
         self.events[0].at_tick
     }
 
     pub fn next_tick(&self) -> Option<u64> {
-        for e in self.events.iter() {
-            if e.called {
-                continue;
-            }
-
-            return Some(e.at_tick);
-        }
-        None
+        // 周期事件触发后 at_tick 后移，队列不再保持有序
+        self.events
+            .iter()
+            .filter(|e| !e.is_done())
+            .map(|e| e.at_tick)
+            .min()
     }
 
-    pub fn pop(&mut self, now: u64) -> Option<&Event> {
+    /// 取出到期事件的回调，回调执行期间可以继续添加事件
+    pub fn pop(&mut self, now: u64) -> Option<Arc<dyn Fn()>> {
         for e in self.events.iter_mut() {
-            if e.called {
+            if e.is_done() {
                 continue;
             }
             if e.at_tick <= now {
@@ -57,7 +82,7 @@ impl Queue {
                 } else {
                     e.called = true;
                 }
-                return Some(e);
+                return Some(e.callback.clone());
             }
         }
         None
@@ -3,8 +3,8 @@ use core::{
     time::Duration,
 };
 
-use super::queue;
-use alloc::boxed::Box;
+use super::queue::{self, TimerHandle};
+use alloc::{boxed::Box, sync::Arc};
 use rdrive::IrqConfig;
 
 const NANO_PER_SEC: u128 = 1_000_000_000;
@@ -29,50 +29,68 @@ impl Timer {
         self.tick_to_duration(self.timer.current_ticks() as _)
     }
 
-    pub fn after(&mut self, duration: Duration, callback: impl Fn() + 'static) {
+    pub fn after(&mut self, duration: Duration, callback: impl Fn() + 'static) -> TimerHandle {
+        let handle = TimerHandle::default();
+        self.after_with(duration, callback, handle.clone());
+        handle
+    }
+
+    /// 同 [`Timer::after`]，使用调用者创建的句柄
+    pub(super) fn after_with(
+        &mut self,
+        duration: Duration,
+        callback: impl Fn() + 'static,
+        handle: TimerHandle,
+    ) {
         let ticks = self.duration_to_tick(duration);
 
         let event = queue::Event {
             interval: None,
             at_tick: self.timer.current_ticks() as u64 + ticks,
-            callback: Box::new(callback),
+            callback: Arc::new(callback),
             called: false,
+            handle,
         };
 
         self.add_event(event);
     }
 
-    pub fn every(&mut self, duration: Duration, callback: impl Fn() + 'static) {
+    pub fn every(&mut self, duration: Duration, callback: impl Fn() + 'static) -> TimerHandle {
         let ticks = self.duration_to_tick(duration);
+        let handle = TimerHandle::default();
 
         let event = queue::Event {
             interval: Some(ticks),
             at_tick: self.timer.current_ticks() as u64 + ticks,
-            callback: Box::new(callback),
+            callback: Arc::new(callback),
             called: false,
+            handle: handle.clone(),
         };
 
         self.add_event(event);
+        handle
     }
 
     fn add_event(&mut self, event: queue::Event) {
         fence(Ordering::SeqCst);
 
         let next_tick = self.q.add_and_next_tick(event);
-        let v = next_tick as usize - self.timer.current_ticks();
+        let v = (next_tick as usize).saturating_sub(self.timer.current_ticks());
         self.timer.set_timeval(v);
 
         fence(Ordering::SeqCst);
     }
 
     pub fn handle_irq(&mut self) {
-        while let Some(event) = self.q.pop(self.timer.current_ticks() as u64) {
-            (event.callback)();
+        while let Some(callback) = self.q.pop(self.timer.current_ticks() as u64) {
+            callback();
         }
 
         match self.q.next_tick() {
             Some(next_tick) => {
-                self.timer.set_timeval(next_tick as _);
+                // timeval 为相对当前的 tick 数
+                let now = self.timer.current_ticks() as u64;
+                self.timer.set_timeval(next_tick.saturating_sub(now) as _);
                 self.set_irq_enable(true);
             }
             None => {
@@ -84,6 +102,11 @@ impl Timer {
     pub fn set_irq_enable(&mut self, enable: bool) {
         self.timer.set_irq_enable(enable);
     }
+    /// 是否还有未触发的事件
+    pub fn has_pending(&self) -> bool {
+        self.q.next_tick().is_some()
+    }
+
     pub fn get_irq_status(&self) -> bool {
         self.timer.get_irq_status()
     }
@@ -15,13 +15,13 @@ impl AsmFmt for String {
             .collect()
     }
 }
-pub fn trap_handle_irq(func: ItemFn) -> proc_macro2::TokenStream {
+pub fn trap_handle_irq(func: ItemFn, lower_el: bool) -> proc_macro2::TokenStream {
     let inner_name = format_ident!("__{}", func.sig.ident);
     let stmts = &func.block.stmts;
     let inputs = &func.sig.inputs;
 
-    let fp = __trap_handle_irq(&func, &inner_name, true);
-    let sp = __trap_handle_irq(&func, &inner_name, false);
+    let fp = __trap_handle_irq(&func, &inner_name, true, lower_el);
+    let sp = __trap_handle_irq(&func, &inner_name, false, lower_el);
 
     quote! {
         #[cfg(hard_float)]
@@ -40,18 +40,19 @@ pub fn __trap_handle_irq(
     func: &ItemFn,
     inner_name: &Ident,
     is_fp: bool,
+    lower_el: bool,
 ) -> proc_macro2::TokenStream {
     let func_name = &func.sig.ident;
     let vis = &func.vis;
 
-    let mut asm_str = trap_store_regs(is_fp);
+    let mut asm_str = trap_store_regs(is_fp, lower_el);
     asm_str += "
     mov x0, sp
     BL {f}
     mov sp, x0
     ";
 
-    asm_str += &trap_restore_regs(is_fp);
+    asm_str += &trap_restore_regs(is_fp, lower_el);
     asm_str += "
     eret
     ";
@@ -109,23 +110,39 @@ pub fn tcb_switch(is_fp: bool) -> proc_macro2::TokenStream {
     }
 }
 
-fn trap_store_regs(is_fp: bool) -> String {
+/// 来自低异常级时 `sp` 字段保存 `SP_EL0`
+fn trap_store_regs(is_fp: bool, lower_el: bool) -> String {
     let mut out = ctx_store_x_q(is_fp);
     out += "
     mrs x10, ELR_EL1
+        ";
+    if lower_el {
+        out += "
+    mrs x9, SP_EL0
+        ";
+    } else {
+        out += "
     mov x9, sp
     sub x9, x9,   #0x10
+        ";
+    }
+    out += "
 	stp x9, x10,  [sp,#-0x10]!
         ";
     out
 }
 
-fn trap_restore_regs(is_fp: bool) -> String {
+fn trap_restore_regs(is_fp: bool, lower_el: bool) -> String {
     let mut out = "
     ldp X0, X10,    [sp], #0x10
     msr	ELR_EL1,    X10
         "
     .to_string();
+    if lower_el {
+        out += "
+    msr SP_EL0,     X0
+        ";
+    }
     out += &ctx_restore_x_q(is_fp);
 
     out
@@ -188,6 +188,9 @@ enum Aarch64TrapHandlerKind {
 #[derive(Debug, FromMeta)]
 struct Aarch64TrapHandlerArgs {
     kind: Aarch64TrapHandlerKind,
+    /// 来自低异常级，保存并恢复 `SP_EL0`
+    #[darling(default)]
+    lower_el: bool,
 }
 
 #[proc_macro_attribute]
@@ -209,9 +212,11 @@ pub fn aarch64_trap_handler(args: TokenStream, input: TokenStream) -> TokenStrea
 
     match args.kind {
         Aarch64TrapHandlerKind::Irq | Aarch64TrapHandlerKind::Fiq => {
-            arch::aarch64::trap_handle_irq(func).into()
+            arch::aarch64::trap_handle_irq(func, args.lower_el).into()
+        }
+        Aarch64TrapHandlerKind::Sync => arch::aarch64::trap_handle_irq(func, args.lower_el).into(),
+        Aarch64TrapHandlerKind::SError => {
+            arch::aarch64::trap_handle_irq(func, args.lower_el).into()
         }
-        Aarch64TrapHandlerKind::Sync => arch::aarch64::trap_handle_irq(func).into(),
-        Aarch64TrapHandlerKind::SError => arch::aarch64::trap_handle_irq(func).into(),
     }
 }
@@ -0,0 +1,44 @@
+//! 沿 x29 帧记录链回溯调用栈
+
+use core::{arch::asm, ops::Range};
+
+/// 帧记录为 `[fp, lr]`，每个记录 16 字节对齐
+const FRAME_RECORD_SIZE: usize = 16;
+
+/// 从调用者开始回溯，只访问当前 sp 与 `stack.end` 之间的帧记录
+pub fn collect(stack: Range<usize>, frames: &mut [usize]) -> usize {
+    let sp: usize;
+    let mut fp: usize;
+    unsafe {
+        asm!("mov {}, sp", out(reg) sp);
+        asm!("mov {}, x29", out(reg) fp);
+    }
+
+    // 运行在异常栈等其他栈上时不回溯
+    if !stack.contains(&sp) {
+        return 0;
+    }
+
+    let mut n = 0;
+    while n < frames.len() {
+        // 未启用帧指针时 x29 可能是任意值，越界即停止
+        if fp < sp || fp + FRAME_RECORD_SIZE > stack.end || !fp.is_multiple_of(FRAME_RECORD_SIZE) {
+            break;
+        }
+        let (next, lr) = unsafe {
+            let record = fp as *const usize;
+            (record.read(), record.add(1).read())
+        };
+        if lr == 0 {
+            break;
+        }
+        frames[n] = lr;
+        n += 1;
+        // 帧记录链只能向栈顶方向延伸
+        if next <= fp {
+            break;
+        }
+        fp = next;
+    }
+    n
+}
@@ -1,15 +1,18 @@
+use core::arch::asm;
+
 use somehal::{BootInfo, mem::phys_to_virt};
 use sparreal_kernel::{globals::PlatformInfoKind, hal_al};
 
 use crate::mem;
 
-use super::debug;
+use super::{debug, trap};
 
 #[somehal::entry]
 fn main(args: &BootInfo) -> ! {
     if let Some(fdt) = args.fdt {
         somehal::println!("FDT at {:p}", fdt.as_ptr());
     }
+    trap::setup();
     debug::setup_by_fdt(args.fdt, phys_to_virt);
     mem::setup_boot_args(args);
 
@@ -17,3 +20,24 @@ fn main(args: &BootInfo) -> ! {
         args.fdt.map_or(0, |fdt| fdt.as_ptr() as usize).into(),
     ));
 }
+
+#[somehal::secondary_entry]
+fn secondary(_cpu_id: usize) {
+    trap::setup();
+
+    // Leave the boot stack reserved by somehal for the kernel stack of this CPU.
+    let stack_top = hal_al::run::secondary_stack_top();
+    unsafe {
+        asm!(
+            "mov sp, {stack}",
+            "bl  {entry}",
+            stack = in(reg) stack_top,
+            entry = sym secondary_main,
+            options(noreturn),
+        )
+    }
+}
+
+extern "C" fn secondary_main() -> ! {
+    hal_al::run::run_secondary()
+}
@@ -0,0 +1,156 @@
+use core::fmt::{self, Display};
+
+use aarch64_cpu::registers::*;
+use log::error;
+use sparreal_kernel::{mem::stack, task};
+
+use super::context::Context;
+
+/// SPSR.I，异常发生时中断已屏蔽
+const SPSR_I: u64 = 1 << 7;
+
+/// 由 ESR_EL1 与 FAR_EL1 描述的异常
+struct Fault {
+    esr: u64,
+    far: u64,
+}
+
+impl Fault {
+    fn read() -> Self {
+        Self {
+            esr: ESR_EL1.get(),
+            far: FAR_EL1.get(),
+        }
+    }
+
+    fn ec(&self) -> u64 {
+        (self.esr >> 26) & 0x3f
+    }
+
+    fn iss(&self) -> u64 {
+        self.esr & 0x1ff_ffff
+    }
+
+    fn class(&self) -> &'static str {
+        match self.ec() {
+            0b00_0000 => "Unknown reason (undefined instruction)",
+            0b00_0001 => "Trapped WFI/WFE",
+            0b00_0111 => "Trapped SIMD/FP access",
+            0b00_1101 => "Branch target exception",
+            0b00_1110 => "Illegal execution state",
+            0b01_0101 => "SVC",
+            0b01_0110 => "HVC",
+            0b01_0111 => "SMC",
+            0b01_1000 => "Trapped MSR/MRS/system instruction",
+            0b01_1001 => "Trapped SVE access",
+            0b01_1100 => "Pointer authentication failure",
+            0b10_0000 => "Instruction abort from lower EL",
+            0b10_0001 => "Instruction abort",
+            0b10_0010 => "PC alignment fault",
+            0b10_0100 => "Data abort from lower EL",
+            0b10_0101 => "Data abort",
+            0b10_0110 => "SP alignment fault",
+            0b10_1100 => "Trapped floating-point exception",
+            0b10_1111 => "SError",
+            0b11_0000 | 0b11_0001 => "Breakpoint",
+            0b11_0010 | 0b11_0011 => "Software step",
+            0b11_0100 | 0b11_0101 => "Watchpoint",
+            0b11_1100 => "BRK instruction",
+            _ => "Unhandled exception class",
+        }
+    }
+
+    fn is_abort(&self) -> bool {
+        matches!(self.ec(), 0b10_0000 | 0b10_0001 | 0b10_0100 | 0b10_0101)
+    }
+
+    fn is_data_abort(&self) -> bool {
+        matches!(self.ec(), 0b10_0100 | 0b10_0101)
+    }
+
+    /// FAR_EL1 只对这些异常有效
+    fn far_valid(&self) -> bool {
+        self.is_abort() || matches!(self.ec(), 0b10_0010 | 0b11_0100 | 0b11_0101)
+    }
+}
+
+/// 指令/数据异常的 DFSC/IFSC
+fn fault_status(fsc: u64) -> (&'static str, Option<u64>) {
+    let level = Some(fsc & 0b11);
+    match fsc {
+        0b00_0000..=0b00_0011 => ("address size fault", level),
+        0b00_0100..=0b00_0111 => ("translation fault", level),
+        0b00_1000..=0b00_1011 => ("access flag fault", level),
+        0b00_1100..=0b00_1111 => ("permission fault", level),
+        0b01_0000 => ("synchronous external abort", None),
+        0b01_0001 => ("tag check fault", None),
+        0b01_1000 => ("synchronous parity or ECC error", None),
+        0b10_0001 => ("alignment fault", None),
+        0b11_0000 => ("TLB conflict abort", None),
+        _ => ("unknown fault status", None),
+    }
+}
+
+impl Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.class())?;
+        if self.is_abort() {
+            let iss = self.iss();
+            if self.is_data_abort() {
+                // WnR，缓存维护指令总是报告为写
+                let write = iss & (1 << 6) != 0 && iss & (1 << 8) == 0;
+                write!(f, " ({})", if write { "write" } else { "read" })?;
+            }
+            let (status, level) = fault_status(iss & 0x3f);
+            write!(f, ": {status}")?;
+            if let Some(level) = level {
+                write!(f, " at level {level}")?;
+            }
+        }
+        if self.far_valid() {
+            write!(f, ", FAR={:#x}", self.far)?;
+        }
+        write!(
+            f,
+            ", ESR={:#x} (EC {:#08b}, ISS {:#x})",
+            self.esr,
+            self.ec(),
+            self.iss()
+        )
+    }
+}
+
+struct CurrentTask;
+
+impl Display for CurrentTask {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match task::try_current() {
+            Some(task) => write!(f, "task {:?} (Pid {:?})", task.name, task.pid),
+            None => write!(f, "boot context"),
+        }
+    }
+}
+
+/// 打印异常报告并结束当前任务。
+///
+/// 内核在屏蔽中断时发生异常可能持有锁，此时无法只结束当前任务，直接 panic。
+pub fn handle(ctx: &Context, from_user: bool) -> ! {
+    let fault = Fault::read();
+    if !from_user && fault.is_data_abort() && stack::is_stack_guard(fault.far as usize) {
+        error!(
+            "stack overflow in {CurrentTask} @ {:p}: {fault}\r\n{ctx:?}",
+            ctx.pc
+        );
+    } else {
+        error!(
+            "{fault} @ {:p} in {CurrentTask}{}\r\n{ctx:?}",
+            ctx.pc,
+            if from_user { " (user mode)" } else { "" },
+        );
+    }
+
+    if !from_user && (ctx.spsr & SPSR_I != 0 || task::try_current().is_none()) {
+        panic!("unrecoverable exception in {CurrentTask}: {fault}");
+    }
+    task::kill_current()
+}
@@ -1,6 +1,11 @@
-use core::{cell::UnsafeCell, ops::Deref};
+use core::{
+    cell::UnsafeCell,
+    ops::Deref,
+    sync::atomic::{AtomicUsize, Ordering},
+};
 
-use alloc::format;
+use aarch64_cpu::asm::barrier;
+use alloc::{collections::btree_map::BTreeMap, format};
 use arm_gic_driver::v2::*;
 use rdif_intc::*;
 use sparreal_kernel::{
@@ -10,7 +15,9 @@ use sparreal_kernel::{
     },
     irq::IrqParam,
     mem::iomap,
+    platform,
 };
+use spin::Mutex;
 
 use crate::arch::gic::{VERSION, id_convert, trigger_convert};
 
@@ -54,6 +61,7 @@ fn probe_gic(info: FdtInfo<'_>, dev: PlatformDevice) -> Result<(), OnProbeError>
         hyper = Some(HyperAddress::new(gich.into(), gicv.into()));
     }
 
+    GICD.store(gicd.as_ptr() as usize, Ordering::Release);
     let gic = unsafe { Gic::new(gicd.into(), gicc.into(), hyper) };
     let cpu = gic.cpu_interface();
     unsafe {
@@ -72,13 +80,27 @@ fn with_gic<R>(id: DeviceId, f: impl FnOnce(&mut Gic) -> R) -> R {
 
 pub fn irq_enable(config: IrqParam) {
     with_gic(config.intc, |gic| {
-        let intid = id_convert(config.cfg.irq);
-        gic.set_irq_enable(intid, true);
-        gic.set_priority(intid, 0);
-        if !intid.is_private() {
-            gic.set_cfg(intid, trigger_convert(config.cfg.trigger));
-            gic.set_target_cpu(intid, TargetList::new([0].into_iter()));
-        }
+        gic.set_irq_enable(id_convert(config.cfg.irq), true);
+    });
+}
+
+pub fn irq_set_priority(id: DeviceId, irq: IrqId, priority: u8) {
+    with_gic(id, |gic| gic.set_priority(id_convert(irq), priority));
+}
+
+pub fn irq_set_trigger(id: DeviceId, irq: IrqId, trigger: rdif_intc::Trigger) {
+    with_gic(id, |gic| {
+        gic.set_cfg(id_convert(irq), trigger_convert(trigger))
+    });
+}
+
+pub fn irq_set_affinity(id: DeviceId, irq: IrqId, cpu_hard_id: usize) {
+    let target = *CPU_IF
+        .lock()
+        .get(&cpu_hard_id)
+        .unwrap_or_else(|| panic!("cpu {cpu_hard_id:#x} has no GIC cpu interface"));
+    with_gic(id, |gic| {
+        gic.set_target_cpu(id_convert(irq), TargetList::new(target.cpu_id_list()))
     });
 }
 
@@ -89,6 +111,14 @@ pub fn irq_disable(id: DeviceId, irq: IrqId) {
     });
 }
 
+/// 分发器的虚拟地址
+static GICD: AtomicUsize = AtomicUsize::new(0);
+/// 各 CPU 的硬件编号到 CPU 接口编号的映射，SPI 的目标以 CPU 接口编号表示
+static CPU_IF: Mutex<BTreeMap<usize, TargetList>> = Mutex::new(BTreeMap::new());
+/// 各 CPU 正在处理的 SGI 的发送方
+static SGI_SOURCE: Mutex<BTreeMap<usize, usize>> = Mutex::new(BTreeMap::new());
+const GICD_SGIR: usize = 0xf00;
+
 static TRAP: TrapOpWarp = TrapOpWarp(UnsafeCell::new(None));
 
 struct TrapOpWarp(UnsafeCell<Option<TrapOp>>);
@@ -108,11 +138,24 @@ impl Deref for TrapOpWarp {
 pub fn init_current_cpu(id: DeviceId) {
     let mut cpu = with_gic(id, |gic| gic.cpu_interface());
     cpu.init_current_cpu();
+
+    // GICD_ITARGETSR0 为只读，读出当前 CPU 接口对应的位
+    let itargetsr0 = (GICD.load(Ordering::Acquire) + 0x800) as *const u8;
+    let mask = unsafe { itargetsr0.read_volatile() };
+    CPU_IF.lock().insert(
+        platform::cpu_hard_id().into(),
+        TargetList::new((0..8).filter(|i| mask & (1 << i) != 0)),
+    );
 }
 
 pub fn ack() -> IrqId {
     (match TRAP.ack() {
-        Ack::SGI { intid, cpu_id: _ } => intid,
+        Ack::SGI { intid, cpu_id } => {
+            SGI_SOURCE
+                .lock()
+                .insert(platform::cpu_hard_id().into(), cpu_id);
+            intid
+        }
         Ack::Other(intid) => intid,
     }
     .to_u32() as usize)
@@ -121,5 +164,25 @@ pub fn ack() -> IrqId {
 
 pub fn eoi(irq: IrqId) {
     let intid = id_convert(irq);
-    TRAP.eoi(Ack::Other(intid));
+    // SGI 的 EOI 须带上发送方的 CPU 接口编号
+    let ack = if intid.is_sgi()
+        && let Some(cpu_id) = SGI_SOURCE.lock().remove(&platform::cpu_hard_id().into())
+    {
+        Ack::SGI { intid, cpu_id }
+    } else {
+        Ack::Other(intid)
+    };
+    TRAP.eoi(ack);
+}
+
+pub fn send_sgi(sgi: IrqId, cpu_hard_id: usize) {
+    let target = *CPU_IF
+        .lock()
+        .get(&cpu_hard_id)
+        .unwrap_or_else(|| panic!("cpu {cpu_hard_id:#x} has no GIC cpu interface"));
+    let sgir = (GICD.load(Ordering::Acquire) + GICD_SGIR) as *mut u32;
+    let val = ((target.as_u8() as u32) << 16) | id_convert(sgi).to_u32();
+    // 直接写寄存器而不经过驱动锁，可在中断上下文中发送
+    barrier::dsb(barrier::ISHST);
+    unsafe { sgir.write_volatile(val) };
 }
@@ -1,3 +1,4 @@
+use aarch64_cpu::asm::barrier;
 use alloc::format;
 use arm_gic_driver::v3::*;
 use rdif_intc::*;
@@ -58,13 +59,26 @@ fn with_gic<R>(id: DeviceId, f: impl FnOnce(&mut Gic) -> R) -> R {
 
 pub fn irq_enable(config: IrqParam) {
     with_gic(config.intc, |gic| {
-        let intid = id_convert(config.cfg.irq);
-        gic.set_irq_enable(intid, true);
-        gic.set_priority(intid, 0);
-        if !intid.is_private() {
-            gic.set_cfg(intid, trigger_convert(config.cfg.trigger));
-            gic.set_target_cpu(intid, Some(Affinity::current()));
-        }
+        gic.set_irq_enable(id_convert(config.cfg.irq), true);
+    });
+}
+
+pub fn irq_set_priority(id: DeviceId, irq: IrqId, priority: u8) {
+    with_gic(id, |gic| gic.set_priority(id_convert(irq), priority));
+}
+
+pub fn irq_set_trigger(id: DeviceId, irq: IrqId, trigger: rdif_intc::Trigger) {
+    with_gic(id, |gic| {
+        gic.set_cfg(id_convert(irq), trigger_convert(trigger))
+    });
+}
+
+pub fn irq_set_affinity(id: DeviceId, irq: IrqId, cpu_hard_id: usize) {
+    with_gic(id, |gic| {
+        gic.set_target_cpu(
+            id_convert(irq),
+            Some(Affinity::from_mpidr(cpu_hard_id as u64)),
+        )
     });
 }
 
@@ -75,6 +89,15 @@ pub fn irq_disable(id: DeviceId, irq: IrqId) {
     });
 }
 
+pub fn send_sgi(sgi: IrqId, cpu_hard_id: usize) {
+    // 发送前写入的数据对目标 CPU 可见
+    barrier::dsb(barrier::ISHST);
+    arm_gic_driver::v3::send_sgi(
+        id_convert(sgi),
+        SGITarget::list([Affinity::from_mpidr(cpu_hard_id as u64)]),
+    );
+}
+
 pub fn init_current_cpu(id: DeviceId) {
     let mut cpu = with_gic(id, |gic| gic.cpu_interface());
     cpu.init_current_cpu().unwrap();
@@ -30,6 +30,42 @@ pub fn irq_disable(id: DeviceId, irq: IrqId) {
     }
 }
 
+pub fn irq_set_priority(id: DeviceId, irq: IrqId, priority: u8) {
+    match version() {
+        2 => gic_v2::irq_set_priority(id, irq, priority),
+        3 => gic_v3::irq_set_priority(id, irq, priority),
+        _ => panic!("Unsupported GIC version"),
+    }
+}
+
+pub fn irq_set_trigger(id: DeviceId, irq: IrqId, trigger: Trigger) {
+    // SGI 固定为边沿触发
+    if id_convert(irq).is_sgi() {
+        return;
+    }
+    match version() {
+        2 => gic_v2::irq_set_trigger(id, irq, trigger),
+        3 => gic_v3::irq_set_trigger(id, irq, trigger),
+        _ => panic!("Unsupported GIC version"),
+    }
+}
+
+pub fn irq_set_affinity(id: DeviceId, irq: IrqId, cpu_hard_id: usize) {
+    match version() {
+        2 => gic_v2::irq_set_affinity(id, irq, cpu_hard_id),
+        3 => gic_v3::irq_set_affinity(id, irq, cpu_hard_id),
+        _ => panic!("Unsupported GIC version"),
+    }
+}
+
+pub fn send_sgi(sgi: IrqId, cpu_hard_id: usize) {
+    match version() {
+        2 => gic_v2::send_sgi(sgi, cpu_hard_id),
+        3 => gic_v3::send_sgi(sgi, cpu_hard_id),
+        _ => panic!("Unsupported GIC version"),
+    }
+}
+
 pub fn init_current_cpu(id: DeviceId) {
     match version() {
         2 => gic_v2::init_current_cpu(id),
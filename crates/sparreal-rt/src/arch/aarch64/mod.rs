@@ -1,15 +1,15 @@
-use core::arch::asm;
+use core::{arch::asm, ops::Range};
 
 use aarch64_cpu::registers::*;
-use aarch64_cpu_ext::cache;
+use aarch64_cpu_ext::{cache, structures::tte::Shareability};
 use context::Context;
-use log::{trace, warn};
+use log::trace;
 use somehal::mem::MapRangeConfig;
 use sparreal_kernel::{
     driver::IrqId,
     hal_al::{
-        CacheOp, DeviceId, DriverRegisterSlice, Hal,
-        mmu::{Access, MapConfig, Mmu, PageTableRef, PagingError},
+        CacheOp, DeviceId, DriverRegisterSlice, Hal, Trigger,
+        mmu::{Access, MapConfig, Mmu, PageFlags, PageTableRef, PagingError, Phys, Virt},
     },
     impl_trait,
     irq::IrqParam,
@@ -22,11 +22,15 @@ use crate::{
     mem::{driver_registers, stack_cpu0},
 };
 
+mod backtrace;
 mod boot;
 mod context;
 mod debug;
+mod fault;
 mod gic;
 mod power;
+mod smmu;
+mod table;
 mod timer;
 mod trap;
 
@@ -75,9 +79,9 @@ impl Mmu for HalImpl {
         Ok(PageTableRef { id: 0, addr: table.paddr().raw().into() })
     }
 
-    fn release_table(_table: PageTableRef, alloc: &mut dyn Access) {
-        let mut _baccess = BAccess(alloc);
-        warn!("release_table is not implemented"); // TODO
+    fn release_table(table: PageTableRef, alloc: &mut dyn Access) {
+        let mut baccess = BAccess(alloc);
+        table::release(table.addr.raw(), &mut baccess);
     }
 
     fn get_kernel_table() -> PageTableRef {
@@ -96,12 +100,7 @@ impl Mmu for HalImpl {
     ) -> Result<(), PagingError> {
         let mut baccess = BAccess(alloc);
 
-        let access = match config.access {
-            AccessSetting::Read => somehal::mem::AccessKind::Read,
-            AccessSetting::ReadWrite => somehal::mem::AccessKind::ReadWrite,
-            AccessSetting::ReadExecute => somehal::mem::AccessKind::ReadExecute,
-            AccessSetting::ReadWriteExecute => somehal::mem::AccessKind::ReadWriteExecute,
-        };
+        let access = access_kind(config.access);
 
         let mut cpu_share = true;
 
@@ -124,12 +123,81 @@ impl Mmu for HalImpl {
             cache,
             cpu_share,
         };
-        somehal::mem::mmu::table_map(map_table(table), &mut baccess, config)?;
-         Ok(())
+        table::map(table.addr.raw(), &mut baccess, config)
+    }
+
+    fn table_unmap(
+        table: PageTableRef,
+        alloc: &mut dyn Access,
+        va_start: Virt<u8>,
+        size: usize,
+    ) -> Result<(), PagingError> {
+        let mut baccess = BAccess(alloc);
+        table::unmap(table.addr.raw(), &mut baccess, va_start.raw(), size)
+    }
+
+    fn table_protect(
+        table: PageTableRef,
+        alloc: &mut dyn Access,
+        va_start: Virt<u8>,
+        size: usize,
+        access: AccessSetting,
+    ) -> Result<(), PagingError> {
+        let mut baccess = BAccess(alloc);
+        table::protect(table.addr.raw(), &mut baccess, va_start.raw(), size, access_kind(access))
+    }
+
+    fn table_translate(
+        table: PageTableRef,
+        alloc: &mut dyn Access,
+        va: Virt<u8>,
+    ) -> Option<(Phys<u8>, PageFlags)> {
+        let baccess = BAccess(alloc);
+        let (pa, tte) = table::translate(table.addr.raw(), &baccess, va.raw())?;
+        let user = tte.access_permission().allows_unprivileged();
+        let write = tte.access_permission().allows_privileged_write();
+        // 用户映射看 UXN，内核映射看 PXN
+        let exec = if user {
+            tte.is_executable()
+        } else {
+            tte.is_privileged_executable()
+        };
+        let access = match (write, exec) {
+            (false, false) => AccessSetting::Read,
+            (true, false) => AccessSetting::ReadWrite,
+            (false, true) => AccessSetting::ReadExecute,
+            (true, true) => AccessSetting::ReadWriteExecute,
+        };
+        let cache = match table::cache_kind(&tte) {
+            somehal::mem::CacheKind::Device => CacheSetting::Device,
+            somehal::mem::CacheKind::NoCache => CacheSetting::NonCacheable,
+            somehal::mem::CacheKind::Normal => match tte.shareability() {
+                Shareability::NonShareable => CacheSetting::PerCpu,
+                _ => CacheSetting::Normal,
+            },
+        };
+        Some((pa.into(), PageFlags { access, cache, user }))
+    }
+
+    fn set_user_table(table: Option<PageTableRef>) {
+        table::set_user_table(table.map(|t| (t.id, t.addr.raw())));
+    }
+
+    fn flush_tlb_asid(asid: usize) {
+        table::flush_tlb_asid(asid);
     }
 }
 }
 
+fn access_kind(access: AccessSetting) -> somehal::mem::AccessKind {
+    match access {
+        AccessSetting::Read => somehal::mem::AccessKind::Read,
+        AccessSetting::ReadWrite => somehal::mem::AccessKind::ReadWrite,
+        AccessSetting::ReadExecute => somehal::mem::AccessKind::ReadExecute,
+        AccessSetting::ReadWriteExecute => somehal::mem::AccessKind::ReadWriteExecute,
+    }
+}
+
 fn map_table(v: PageTableRef) -> somehal::mem::PageTable {
     somehal::mem::PageTable {
         id: v.id,
@@ -155,12 +223,13 @@ impl Hal for HalImpl {
         crate::mem::boot_regions().get(index).cloned()
     }
 
+    // SP_EL0 保存用户栈，当前任务放在 TPIDR_EL1
     unsafe fn get_current_tcb_addr() -> *mut u8 {
-        SP_EL0.get() as usize as _
+        TPIDR_EL1.get() as usize as _
     }
 
     unsafe fn set_current_tcb_addr(addr: *mut u8) {
-        SP_EL0.set(addr as usize as _);
+        TPIDR_EL1.set(addr as usize as _);
     }
 
     unsafe fn cpu_context_sp(ctx_ptr: *const u8) -> usize {
@@ -189,10 +258,22 @@ impl Hal for HalImpl {
         unsafe { __tcb_switch(prev_ptr, next_ptr) };
     }
 
+    unsafe fn user_enter(pc: usize, user_sp: usize, kstack_top: usize, arg: usize) -> ! {
+        unsafe { trap::user_enter(pc, user_sp, kstack_top, arg) }
+    }
+
+    fn backtrace(stack: Range<usize>, frames: &mut [usize]) -> usize {
+        backtrace::collect(stack, frames)
+    }
+
     fn wait_for_interrupt() {
         aarch64_cpu::asm::wfi();
     }
 
+    fn cpu_on(cpu_id: usize) -> Result<(), &'static str> {
+        power::cpu_on(cpu_id)
+    }
+
     fn irq_init_current_cpu(id: DeviceId) {
         gic::init_current_cpu(id);
     }
@@ -225,6 +306,22 @@ impl Hal for HalImpl {
         gic::irq_disable(id, irq);
     }
 
+    fn irq_set_priority(id: DeviceId, irq: IrqId, priority: u8) {
+        gic::irq_set_priority(id, irq, priority);
+    }
+
+    fn irq_set_trigger(id: DeviceId, irq: IrqId, trigger: Trigger) {
+        gic::irq_set_trigger(id, irq, trigger);
+    }
+
+    fn irq_set_affinity(id: DeviceId, irq: IrqId, cpu_hard_id: usize) {
+        gic::irq_set_affinity(id, irq, cpu_hard_id);
+    }
+
+    fn irq_send_sgi(_id: DeviceId, sgi: IrqId, cpu_hard_id: usize) {
+        gic::send_sgi(sgi, cpu_hard_id);
+    }
+
     fn shutdown() -> ! {
         somehal::power::shutdown()
     }
@@ -76,3 +76,16 @@ fn probe(info: FdtInfo<'_>, plat_dev: PlatformDevice) -> Result<(), OnProbeError
     debug!("PCSI [{method:?}]");
     Ok(())
 }
+
+/// Power on `cpu_id` with PSCI CPU_ON.
+///
+/// somehal cleans the kernel image to memory and starts the CPU at
+/// `_start_secondary` with its MMU off, on the boot stack somehal reserved
+/// for it, which then calls `__pie_boot_secondary`.
+pub fn cpu_on(cpu_id: usize) -> Result<(), &'static str> {
+    let stack_top = somehal::mem::cpu_stack(cpu_id).end;
+    somehal::power::cpu_on(cpu_id as _, stack_top as _).map_err(|e| {
+        error!("CPU_ON {cpu_id:#x} failed: {e}");
+        "psci cpu_on failed"
+    })
+}
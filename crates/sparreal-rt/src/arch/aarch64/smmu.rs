@@ -0,0 +1,359 @@
+//! SMMUv3 驱动。
+//!
+//! 使用线性 stream 表，未绑定的 stream 旁路翻译；绑定后使用 stage 1 翻译，
+//! 每个 stream 一个上下文描述符，页表与 CPU 页表格式相同。
+//! stream 表与命令队列分配在一致性 DMA 内存中，SMMU 以不可缓存方式访问。
+
+use core::hint::spin_loop;
+
+use aarch64_cpu::{asm::barrier, registers::*};
+use alloc::{collections::btree_map::BTreeMap, format, string::String, sync::Arc};
+use log::{debug, error};
+use sparreal_kernel::{
+    driver::{PlatformDevice, module_driver, probe::OnProbeError, register::*},
+    hal_al::mmu::PageTableRef,
+    irq::NoIrqGuard,
+    mem::{
+        dma::{DmaCoherent, alloc_coherent},
+        iommu::{self, IommuError, IommuOps, StreamId},
+        ioremap::{MmioRegion, ioremap},
+        mmu::CacheSetting,
+    },
+};
+use spin::Mutex;
+
+module_driver!(
+    name: "SMMUv3",
+    level: ProbeLevel::PostKernel,
+    // 须先于其后的设备探测
+    priority: ProbePriority(ProbePriority::INTC.0 + 1),
+    probe_kinds: &[
+        ProbeKind::Fdt {
+            compatibles: &["arm,smmu-v3"],
+            on_probe: probe_smmu
+        }
+    ],
+);
+
+const IDR0: usize = 0x0;
+const IDR1: usize = 0x4;
+const IDR5: usize = 0x14;
+const CR0: usize = 0x20;
+const CR0ACK: usize = 0x24;
+const CR1: usize = 0x28;
+const CR2: usize = 0x2c;
+const STRTAB_BASE: usize = 0x80;
+const STRTAB_BASE_CFG: usize = 0x88;
+const CMDQ_BASE: usize = 0x90;
+const CMDQ_PROD: usize = 0x98;
+const CMDQ_CONS: usize = 0x9c;
+
+const IDR0_S1P: u32 = 1 << 1;
+/// 支持 AArch64 格式的页表
+const IDR0_TTF_AARCH64: u32 = 1 << 3;
+/// 页表遍历与 CPU 缓存一致
+const IDR0_COHACC: u32 = 1 << 4;
+const IDR5_GRAN4K: u32 = 1 << 4;
+
+const CR0_SMMUEN: u32 = 1 << 0;
+const CR0_CMDQEN: u32 = 1 << 3;
+/// 无效 stream 上报事件，使用 stage 1 的私有 TLB 范围
+const CR2_RECINVSID: u32 = 1 << 1;
+const CR2_PTM: u32 = 1 << 2;
+
+const Q_BASE_RWA: u64 = 1 << 62;
+const Q_ADDR_MASK: u64 = 0x000f_ffff_ffff_ffe0;
+const CMDQ_CONS_ERR_SHIFT: u32 = 24;
+const CMDQ_CONS_ERR_MASK: u32 = 0x7f;
+
+/// stream 表项 64 字节
+const STE_DWORDS: usize = 8;
+const STE_V: u64 = 1;
+const STE_CFG_BYPASS: u64 = 0b100 << 1;
+const STE_CFG_S1: u64 = 0b101 << 1;
+const STE_S1_CTX_MASK: u64 = 0x000f_ffff_ffff_ffc0;
+/// word1：上下文描述符以可缓存、内部共享方式访问
+const STE1_S1C_CACHE: u64 = (1 << 2) | (1 << 4) | (3 << 6);
+/// word1：旁路时使用设备请求自带的共享属性
+const STE1_SHCFG_INCOMING: u64 = 1 << 44;
+/// word1：访问均视为特权访问，使 EL1 页表项可用
+const STE1_PRIVCFG_PRIV: u64 = 0b11 << 48;
+
+const CD_DWORDS: usize = 8;
+/// 48 位输入地址，4K 粒度，页表遍历可缓存、内部共享
+const CD0_TCR: u64 = 16 | (1 << 8) | (1 << 10) | (3 << 12);
+const CD0_EPD1: u64 = 1 << 30;
+const CD0_V: u64 = 1 << 31;
+const CD0_IPS_SHIFT: u64 = 32;
+const CD0_AA64: u64 = 1 << 41;
+/// 翻译失败时记录并以 abort 终止访问
+const CD0_R: u64 = 1 << 45;
+const CD0_A: u64 = 1 << 46;
+/// ASID 不与 CPU 共享，不受 CPU 的 TLB 广播影响
+const CD0_ASET: u64 = 1 << 47;
+const CD0_ASID_SHIFT: u64 = 48;
+const CD1_TTB0_MASK: u64 = 0x000f_ffff_ffff_fff0;
+
+const CMD_CFGI_STE: u64 = 0x03;
+const CMD_CFGI_ALL: u64 = 0x04;
+const CMD_TLBI_NH_ASID: u64 = 0x11;
+const CMD_TLBI_NSNH_ALL: u64 = 0x30;
+const CMD_SYNC: u64 = 0x46;
+
+/// 线性 stream 表最多 2^16 项
+const MAX_SID_BITS: u32 = 16;
+const MAX_CMDQ_SHIFT: u32 = 8;
+const POLL_LIMIT: usize = 1_000_000;
+
+fn probe_smmu(info: FdtInfo<'_>, _dev: PlatformDevice) -> Result<(), OnProbeError> {
+    let node = info.node;
+    let reg = node
+        .reg()
+        .and_then(|mut r| r.next())
+        .ok_or(OnProbeError::other(format!("[{}] has no reg", node.name())))?;
+    let phandle = node.phandle().ok_or(OnProbeError::other(format!(
+        "[{}] has no phandle",
+        node.name()
+    )))?;
+
+    let regs = ioremap(
+        (reg.address as usize).into(),
+        reg.size.unwrap_or(0x20000),
+        CacheSetting::Device,
+    )
+    .map_err(|e| OnProbeError::other(format!("[{}] ioremap failed: {e:?}", node.name())))?;
+
+    let smmu = Smmu::new(regs).map_err(OnProbeError::other)?;
+    debug!(
+        "SMMUv3 [{}] {:#x}: {} stream bits",
+        node.name(),
+        reg.address,
+        smmu.sid_bits
+    );
+    iommu::register(phandle.as_usize() as u32, Arc::new(smmu));
+    Ok(())
+}
+
+struct CmdQueue {
+    mem: DmaCoherent,
+    shift: u32,
+    prod: u32,
+}
+
+struct Smmu {
+    regs: MmioRegion,
+    strtab: DmaCoherent,
+    sid_bits: u32,
+    /// 输出地址宽度，与 TCR.IPS 的编码相同
+    ips: u64,
+    cmdq: Mutex<CmdQueue>,
+    /// 已绑定 stream 的上下文描述符
+    cds: Mutex<BTreeMap<StreamId, DmaCoherent>>,
+}
+
+impl Smmu {
+    fn new(regs: MmioRegion) -> Result<Self, String> {
+        let idr0 = regs.read::<u32>(IDR0);
+        let idr1 = regs.read::<u32>(IDR1);
+        let idr5 = regs.read::<u32>(IDR5);
+        if idr0 & IDR0_S1P == 0 || idr0 & IDR0_TTF_AARCH64 == 0 || idr5 & IDR5_GRAN4K == 0 {
+            return Err(format!(
+                "stage 1 with 4K AArch64 tables unsupported, IDR0 {idr0:#x}"
+            ));
+        }
+        // CPU 经可缓存的线性映射写页表，SMMU 须能看到缓存中的数据
+        if idr0 & IDR0_COHACC == 0 {
+            return Err(String::from("non-coherent table walk unsupported"));
+        }
+
+        let sid_bits = (idr1 & 0x3f).min(MAX_SID_BITS);
+        let cmdq_shift = ((idr1 >> 21) & 0x1f).min(MAX_CMDQ_SHIFT);
+        let ips = (idr5 & 0b111).min(0b101) as u64;
+
+        let strtab = alloc_coherent((1 << sid_bits) * STE_DWORDS * 8, u64::MAX)
+            .map_err(|e| format!("no memory for stream table: {e:?}"))?;
+        let cmdq = alloc_coherent((1 << cmdq_shift) * 16, u64::MAX)
+            .map_err(|e| format!("no memory for command queue: {e:?}"))?;
+
+        let smmu = Self {
+            regs,
+            strtab,
+            sid_bits,
+            ips,
+            cmdq: Mutex::new(CmdQueue {
+                mem: cmdq,
+                shift: cmdq_shift,
+                prod: 0,
+            }),
+            cds: Mutex::new(BTreeMap::new()),
+        };
+        smmu.reset()?;
+        Ok(smmu)
+    }
+
+    fn reset(&self) -> Result<(), String> {
+        self.write_cr0(0)?;
+
+        // 队列与表均在不可缓存内存中
+        self.regs.write::<u32>(CR1, 0);
+        self.regs.write::<u32>(CR2, CR2_RECINVSID | CR2_PTM);
+
+        for sid in 0..1 << self.sid_bits {
+            self.write_ste(sid, [STE_V | STE_CFG_BYPASS, STE1_SHCFG_INCOMING]);
+        }
+        self.regs.write::<u64>(
+            STRTAB_BASE,
+            Q_BASE_RWA | (self.strtab.bus_addr() & STE_S1_CTX_MASK),
+        );
+        // 线性格式
+        self.regs.write::<u32>(STRTAB_BASE_CFG, self.sid_bits);
+
+        let cmdq = self.cmdq.lock();
+        self.regs.write::<u64>(
+            CMDQ_BASE,
+            Q_BASE_RWA | (cmdq.mem.bus_addr() & Q_ADDR_MASK) | cmdq.shift as u64,
+        );
+        self.regs.write::<u32>(CMDQ_PROD, 0);
+        self.regs.write::<u32>(CMDQ_CONS, 0);
+        drop(cmdq);
+
+        self.write_cr0(CR0_CMDQEN)?;
+        self.submit(&[[CMD_CFGI_ALL, 31], [CMD_TLBI_NSNH_ALL, 0]]);
+        self.write_cr0(CR0_CMDQEN | CR0_SMMUEN)
+    }
+
+    fn write_cr0(&self, val: u32) -> Result<(), String> {
+        self.regs.write::<u32>(CR0, val);
+        for _ in 0..POLL_LIMIT {
+            if self.regs.read::<u32>(CR0ACK) == val {
+                return Ok(());
+            }
+            spin_loop();
+        }
+        Err(format!("CR0 {val:#x} not acknowledged"))
+    }
+
+    fn ste_ptr(&self, sid: StreamId) -> *mut u64 {
+        unsafe { (self.strtab.as_ptr().as_ptr() as *mut u64).add(sid as usize * STE_DWORDS) }
+    }
+
+    /// 写入 stream 表项，word0 最后写入使整项同时生效
+    fn write_ste(&self, sid: StreamId, words: [u64; 2]) {
+        let ste = self.ste_ptr(sid);
+        unsafe {
+            for i in (1..STE_DWORDS).rev() {
+                ste.add(i).write_volatile(if i == 1 { words[1] } else { 0 });
+            }
+            barrier::dmb(barrier::OSHST);
+            ste.write_volatile(words[0]);
+        }
+    }
+
+    /// 修改正在使用的表项：先置为无效并使 SMMU 丢弃缓存，再写入新值
+    fn update_ste(&self, sid: StreamId, words: [u64; 2]) {
+        unsafe { self.ste_ptr(sid).write_volatile(0) };
+        self.submit(&[[CMD_CFGI_STE | (sid as u64) << 32, 1]]);
+        self.write_ste(sid, words);
+        self.submit(&[[CMD_CFGI_STE | (sid as u64) << 32, 1]]);
+    }
+
+    /// 提交命令并等待 SMMU 执行完毕
+    fn submit(&self, cmds: &[[u64; 2]]) {
+        let _g = NoIrqGuard::new();
+        let mut q = self.cmdq.lock();
+        let entries = q.mem.as_ptr().as_ptr() as *mut [u64; 2];
+        let index_mask = (1u32 << q.shift) - 1;
+        let wrap_mask = (1u32 << (q.shift + 1)) - 1;
+
+        for cmd in cmds.iter().chain(core::iter::once(&[CMD_SYNC, 0])) {
+            // 队列满时等待 SMMU 消费
+            while {
+                let cons = self.regs.read::<u32>(CMDQ_CONS) & wrap_mask;
+                (q.prod & index_mask) == (cons & index_mask) && q.prod != cons
+            } {
+                spin_loop();
+            }
+            unsafe {
+                entries
+                    .add((q.prod & index_mask) as usize)
+                    .write_volatile(*cmd)
+            };
+            q.prod = (q.prod + 1) & wrap_mask;
+        }
+        barrier::dsb(barrier::OSHST);
+        self.regs.write::<u32>(CMDQ_PROD, q.prod);
+
+        for _ in 0..POLL_LIMIT {
+            let cons = self.regs.read::<u32>(CMDQ_CONS);
+            let err = (cons >> CMDQ_CONS_ERR_SHIFT) & CMDQ_CONS_ERR_MASK;
+            if err != 0 {
+                panic!("SMMU command error {err:#x} at {:#x}", cons & wrap_mask);
+            }
+            if cons & wrap_mask == q.prod {
+                return;
+            }
+            spin_loop();
+        }
+        panic!("SMMU command queue timeout");
+    }
+}
+
+impl IommuOps for Smmu {
+    fn attach(&self, stream: StreamId, table: PageTableRef, asid: u16) -> Result<(), IommuError> {
+        if stream >= 1 << self.sid_bits {
+            return Err(IommuError::InvalidStream(stream));
+        }
+        let mut cds = self.cds.lock();
+        if cds.contains_key(&stream) {
+            return Err(IommuError::Busy(stream));
+        }
+
+        let cd_mem = alloc_coherent(CD_DWORDS * 8, u64::MAX).map_err(|e| {
+            error!("no memory for context descriptor: {e:?}");
+            IommuError::NoIoSpace
+        })?;
+        let cd = cd_mem.as_ptr().as_ptr() as *mut u64;
+        unsafe {
+            cd.add(1)
+                .write_volatile(table.addr.raw() as u64 & CD1_TTB0_MASK);
+            // 页表项的内存属性索引与 CPU 相同
+            cd.add(3).write_volatile(MAIR_EL1.get());
+            barrier::dmb(barrier::OSHST);
+            cd.write_volatile(
+                CD0_TCR
+                    | CD0_EPD1
+                    | CD0_V
+                    | self.ips << CD0_IPS_SHIFT
+                    | CD0_AA64
+                    | CD0_R
+                    | CD0_A
+                    | CD0_ASET
+                    | (asid as u64) << CD0_ASID_SHIFT,
+            );
+        }
+
+        self.update_ste(
+            stream,
+            [
+                STE_V | STE_CFG_S1 | (cd_mem.bus_addr() & STE_S1_CTX_MASK),
+                STE1_S1C_CACHE | STE1_PRIVCFG_PRIV,
+            ],
+        );
+        self.submit(&[[CMD_TLBI_NH_ASID | (asid as u64) << 48, 0]]);
+        cds.insert(stream, cd_mem);
+        Ok(())
+    }
+
+    fn detach(&self, stream: StreamId) {
+        let mut cds = self.cds.lock();
+        if let Some(old) = cds.remove(&stream) {
+            self.update_ste(stream, [STE_V | STE_CFG_BYPASS, STE1_SHCFG_INCOMING]);
+            // STE 失效完成后 SMMU 不再读取旧的 CD
+            drop(old);
+        }
+    }
+
+    fn flush_asid(&self, asid: u16) {
+        self.submit(&[[CMD_TLBI_NH_ASID | (asid as u64) << 48, 0]]);
+    }
+}
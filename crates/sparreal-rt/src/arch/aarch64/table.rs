@@ -0,0 +1,275 @@
+//! somehal 未覆盖的页表操作：用户页表映射、解除映射、权限修改与 ASID
+
+use core::alloc::Layout;
+
+use aarch64_cpu::{asm::barrier, registers::*};
+use aarch64_cpu_ext::{
+    asm::tlb::{ASIDE1IS, VAAE1IS, VMALLE1IS, tlbi},
+    structures::tte::{AccessPermission, TTE4K48},
+};
+use page_table_generic::{Access, MapConfig, PageTableRef, PagingError};
+use somehal::mem::{
+    AccessKind, CacheKind, MapRangeConfig,
+    mmu::{Table, Tte},
+};
+
+/// 低半部分由 TTBR0 翻译，属于用户地址空间
+const USER_SPACE_END: usize = 1 << 48;
+const TABLE_LEVEL: usize = 4;
+/// 描述符中输出地址所在的位 [47:12]
+const OA_MASK: u64 = 0x0000_ffff_ffff_f000;
+const DESC_TYPE_MASK: u64 = 0b11;
+const DESC_BLOCK: u64 = 0b01;
+const DESC_TABLE_OR_PAGE: u64 = 0b11;
+/// 与 somehal 设置的 MAIR 一致
+const MAIR_DEVICE: u64 = 0;
+const MAIR_NO_CACHE: u64 = 2;
+const PAGE_SHIFT: usize = 12;
+const ENTRY_SHIFT: usize = 9;
+
+pub fn map(
+    table: usize,
+    access: &mut impl Access,
+    config: MapRangeConfig,
+) -> Result<(), PagingError> {
+    let vaddr = config.vaddr as usize;
+    let paddr = config.paddr;
+    let size = config.size;
+    let (write, exec) = permission(config.access);
+    let mut tte = Tte::from(config);
+    set_permission(&mut tte, vaddr, write, exec);
+
+    let mut table: Table = PageTableRef::root_from_addr(table.into());
+    unsafe {
+        table.map(
+            MapConfig::new(vaddr.into(), paddr.into(), size, tte, true, true),
+            access,
+        )
+    }
+}
+
+/// 解除 `[vaddr, vaddr + size)` 的映射，未映射的部分跳过，不释放中间页表
+pub fn unmap(
+    table: usize,
+    access: &mut impl Access,
+    vaddr: usize,
+    size: usize,
+) -> Result<(), PagingError> {
+    for_each_leaf(table, access, vaddr, size, |entry, va| {
+        *entry = 0;
+        barrier::dsb(barrier::ISHST);
+        tlbi(VAAE1IS::new(va));
+    })
+}
+
+/// 修改 `[vaddr, vaddr + size)` 的访问权限，未映射的部分跳过
+pub fn protect(
+    table: usize,
+    access: &mut impl Access,
+    vaddr: usize,
+    size: usize,
+    kind: AccessKind,
+) -> Result<(), PagingError> {
+    let (write, exec) = permission(kind);
+    for_each_leaf(table, access, vaddr, size, |entry, va| {
+        let mut tte = TTE4K48::new(*entry);
+        set_permission(&mut tte, va, write, exec);
+
+        // 只修改权限位，无需先使映射失效
+        *entry = tte.get();
+        barrier::dsb(barrier::ISHST);
+        tlbi(VAAE1IS::new(va));
+    })
+}
+
+/// 返回是否可写、可执行
+fn permission(kind: AccessKind) -> (bool, bool) {
+    let write = matches!(kind, AccessKind::ReadWrite | AccessKind::ReadWriteExecute);
+    let exec = matches!(kind, AccessKind::ReadExecute | AccessKind::ReadWriteExecute);
+    (write, exec)
+}
+
+/// 按地址所属的一半设置访问权限。
+///
+/// 用户映射 EL0 可访问、EL1 不可执行且非全局；内核映射只允许 EL1 访问，EL0 不可执行。
+fn set_permission(tte: &mut TTE4K48, vaddr: usize, write: bool, exec: bool) {
+    if vaddr < USER_SPACE_END {
+        tte.set_access_permission(if write {
+            AccessPermission::ReadWrite
+        } else {
+            AccessPermission::ReadOnly
+        });
+        tte.set_executable(exec);
+        tte.set_privileged_executable(false);
+        tte.set_not_global();
+    } else {
+        tte.set_access_permission(if write {
+            AccessPermission::PrivilegedReadWrite
+        } else {
+            AccessPermission::PrivilegedReadOnly
+        });
+        tte.set_executable(false);
+        tte.set_privileged_executable(exec);
+    }
+}
+
+/// 查找 `vaddr` 的映射，返回物理地址与描述符
+pub fn translate(table: usize, access: &impl Access, vaddr: usize) -> Option<(usize, TTE4K48)> {
+    let mut table = table;
+    for level in (1..=TABLE_LEVEL).rev() {
+        let (entry, entry_size) = entry_of(table, access, vaddr, level);
+        let tte = TTE4K48::new(unsafe { *entry });
+        if !tte.is_valid() {
+            return None;
+        }
+        if level == 1 || tte.is_block() {
+            return Some((tte.address() as usize + (vaddr & (entry_size - 1)), tte));
+        }
+        table = tte.address() as usize;
+    }
+    unreachable!()
+}
+
+/// 描述符的内存类型
+pub fn cache_kind(tte: &TTE4K48) -> CacheKind {
+    match tte.attr_index() {
+        MAIR_DEVICE => CacheKind::Device,
+        MAIR_NO_CACHE => CacheKind::NoCache,
+        _ => CacheKind::Normal,
+    }
+}
+
+/// 释放页表自身及所有中间页表，不释放映射的内存
+pub fn release(table: usize, access: &mut impl Access) {
+    let mut table: Table = PageTableRef::root_from_addr(table.into());
+    table.release(access);
+}
+
+fn entry_of(table: usize, access: &impl Access, va: usize, level: usize) -> (*mut u64, usize) {
+    let shift = PAGE_SHIFT + ENTRY_SHIFT * (level - 1);
+    let idx = (va >> shift) & ((1 << ENTRY_SHIFT) - 1);
+    let entry = unsafe { (access.phys_to_mut(table.into()) as *mut u64).add(idx) };
+    (entry, 1 << shift)
+}
+
+/// 对 `[vaddr, vaddr + size)` 内每个已映射的叶子项调用 `f`，部分覆盖的块映射先拆分
+fn for_each_leaf(
+    table: usize,
+    access: &mut impl Access,
+    vaddr: usize,
+    size: usize,
+    mut f: impl FnMut(&mut u64, usize),
+) -> Result<(), PagingError> {
+    let page_size = 1 << PAGE_SHIFT;
+    if !vaddr.is_multiple_of(page_size) {
+        return Err(PagingError::NotAligned("vaddr"));
+    }
+    if !size.is_multiple_of(page_size) {
+        return Err(PagingError::NotAligned("size"));
+    }
+
+    let end = vaddr + size;
+    let mut va = vaddr;
+    while va < end {
+        va += leaf_step(table, access, va, end, &mut f)?;
+    }
+    barrier::dsb(barrier::ISH);
+    barrier::isb(barrier::SY);
+    Ok(())
+}
+
+/// 处理 `va` 所在的叶子项，返回处理的字节数
+fn leaf_step(
+    mut table: usize,
+    access: &mut impl Access,
+    va: usize,
+    end: usize,
+    f: &mut impl FnMut(&mut u64, usize),
+) -> Result<usize, PagingError> {
+    let mut level = TABLE_LEVEL;
+    loop {
+        let (entry, entry_size) = entry_of(table, access, va, level);
+        let entry = unsafe { &mut *entry };
+        let tte = TTE4K48::new(*entry);
+
+        if !tte.is_valid() {
+            let next = (va & !(entry_size - 1)) + entry_size;
+            return Ok(next.min(end) - va);
+        }
+
+        if level == 1 || tte.is_block() {
+            if va.is_multiple_of(entry_size) && va + entry_size <= end {
+                f(entry, va);
+                return Ok(entry_size);
+            }
+            // 块映射只覆盖了一部分，拆成下一级后重新处理
+            split_block(entry, level, access)?;
+            continue;
+        }
+
+        table = tte.address() as usize;
+        level -= 1;
+    }
+}
+
+/// 将 `level` 级的块映射拆成下一级页表，属性保持不变
+fn split_block(entry: &mut u64, level: usize, access: &mut impl Access) -> Result<(), PagingError> {
+    let page_size = 1 << PAGE_SHIFT;
+    let layout = Layout::from_size_align(page_size, page_size).unwrap();
+    let new = unsafe { access.alloc(layout) }.ok_or(PagingError::NoMemory)?;
+
+    let block = TTE4K48::new(*entry);
+    let base = block.address();
+    let attrs = block.get() & !OA_MASK & !DESC_TYPE_MASK;
+    let child_size = 1u64 << (PAGE_SHIFT + ENTRY_SHIFT * (level - 2));
+    // 最后一级的页描述符与块描述符的类型位不同
+    let child_type = if level - 1 == 1 {
+        DESC_TABLE_OR_PAGE
+    } else {
+        DESC_BLOCK
+    };
+
+    let entries = access.phys_to_mut(new) as *mut u64;
+    for i in 0..1 << ENTRY_SHIFT {
+        unsafe {
+            entries
+                .add(i)
+                .write(attrs | (base + i as u64 * child_size) | child_type)
+        };
+    }
+
+    // break-before-make：先使块描述符失效并清除 TLB，再装入页表，避免同一地址同时命中新旧两种大小的映射
+    barrier::dsb(barrier::ISHST);
+    *entry = 0;
+    barrier::dsb(barrier::ISHST);
+    tlbi(VMALLE1IS);
+    barrier::dsb(barrier::ISH);
+    *entry = new.raw() as u64 | DESC_TABLE_OR_PAGE;
+    barrier::dsb(barrier::ISHST);
+    barrier::isb(barrier::SY);
+    Ok(())
+}
+
+/// 切换当前CPU的用户页表，`None` 时禁止 TTBR0 页表遍历
+pub fn set_user_table(table: Option<(usize, usize)>) {
+    match table {
+        Some((asid, addr)) => {
+            TTBR0_EL1
+                .write(TTBR0_EL1::ASID.val(asid as u64) + TTBR0_EL1::BADDR.val((addr >> 1) as u64));
+            TCR_EL1.modify(TCR_EL1::EPD0::EnableTTBR0Walks);
+        }
+        None => {
+            TCR_EL1.modify(TCR_EL1::EPD0::DisableTTBR0Walks);
+            TTBR0_EL1.set(0);
+        }
+    }
+    barrier::isb(barrier::SY);
+}
+
+/// 无效化所有CPU上带 `asid` 标记的 TLB 项
+pub fn flush_tlb_asid(asid: usize) {
+    barrier::dsb(barrier::ISHST);
+    tlbi(ASIDE1IS::new(asid));
+    barrier::dsb(barrier::ISH);
+    barrier::isb(barrier::SY);
+}
@@ -1,4 +1,217 @@
-#[somehal::irq_handler]
-fn irq_handler() {
+use core::arch::{asm, global_asm};
+
+use aarch64_cpu::registers::*;
+use sparreal_kernel::task::TaskControlBlockData;
+use sparreal_macros::aarch64_trap_handler;
+
+use super::{context::Context, fault};
+
+#[aarch64_trap_handler(kind = "irq")]
+fn trap_irq(ctx: &Context) -> usize {
     sparreal_kernel::irq::handle_irq();
+    ctx as *const _ as usize
+}
+
+#[aarch64_trap_handler(kind = "fiq")]
+fn trap_fiq(ctx: &Context) -> usize {
+    ctx as *const _ as usize
+}
+
+#[aarch64_trap_handler(kind = "sync")]
+fn trap_sync(ctx: &Context) -> usize {
+    fault::handle(ctx, false)
+}
+
+#[aarch64_trap_handler(kind = "serror")]
+fn trap_serror(ctx: &Context) -> usize {
+    fault::handle(ctx, false)
+}
+
+#[aarch64_trap_handler(kind = "irq", lower_el = true)]
+fn trap_irq_lower(ctx: &Context) -> usize {
+    sparreal_kernel::irq::handle_irq();
+    ctx as *const _ as usize
+}
+
+#[aarch64_trap_handler(kind = "fiq", lower_el = true)]
+fn trap_fiq_lower(ctx: &Context) -> usize {
+    ctx as *const _ as usize
+}
+
+#[aarch64_trap_handler(kind = "sync", lower_el = true)]
+fn trap_sync_lower(ctx: &mut Context) -> usize {
+    let esr = ESR_EL1.extract();
+    match esr.read_as_enum(ESR_EL1::EC) {
+        Some(ESR_EL1::EC::Value::SVC64) => {
+            let x = &ctx.x;
+            let ret =
+                sparreal_kernel::syscall::dispatch(x[8], [x[0], x[1], x[2], x[3], x[4], x[5]]);
+            ctx.x[0] = ret as usize;
+        }
+        _ => fault::handle(ctx, true),
+    }
+    ctx as *const _ as usize
+}
+
+#[aarch64_trap_handler(kind = "serror", lower_el = true)]
+fn trap_serror_lower(ctx: &Context) -> usize {
+    fault::handle(ctx, true)
+}
+
+global_asm!(
+    "
+.section .text.trap
+.balign 0x800
+.global __sparreal_vectors
+__sparreal_vectors:
+    // 当前 EL 使用 SP_EL0，内核不会使用
+    .balign 0x80
+    B {sync}
+    .balign 0x80
+    B {irq}
+    .balign 0x80
+    B {fiq}
+    .balign 0x80
+    B {serror}
+
+    // 当前 EL 使用 SP_ELx
+    .balign 0x80
+    // 内核中 SP_EL0 不保存状态，用作暂存寄存器。
+    // 栈溢出时在原栈上保存现场会再次触发异常，改在当前任务的栈顶处理，该任务随后被结束
+    msr  SP_EL0, x0
+    mrs  x0, ESR_EL1
+    lsr  x0, x0, #26
+    cmp  x0, #0x25
+    b.ne 1f
+    mrs  x0, FAR_EL1
+    sub  x0, sp, x0
+    add  x0, x0, #0x10, lsl #12
+    cmp  x0, #0x20, lsl #12
+    b.hs 1f
+    mrs  x0, TPIDR_EL1
+    cbz  x0, 1f
+    ldr  x0, [x0, #{kstack_top}]
+    mov  sp, x0
+1:
+    mrs  x0, SP_EL0
+    B {sync}
+    .balign 0x80
+    B {irq}
+    .balign 0x80
+    B {fiq}
+    .balign 0x80
+    B {serror}
+
+    // 来自 AArch64 用户态
+    .balign 0x80
+    B {sync_lower}
+    .balign 0x80
+    B {irq_lower}
+    .balign 0x80
+    B {fiq_lower}
+    .balign 0x80
+    B {serror_lower}
+
+    // 不支持 AArch32 用户态
+    .balign 0x80
+    B .
+    .balign 0x80
+    B .
+    .balign 0x80
+    B .
+    .balign 0x80
+    B .
+",
+    kstack_top = const core::mem::offset_of!(TaskControlBlockData, kstack_top),
+    sync = sym trap_sync,
+    irq = sym trap_irq,
+    fiq = sym trap_fiq,
+    serror = sym trap_serror,
+    sync_lower = sym trap_sync_lower,
+    irq_lower = sym trap_irq_lower,
+    fiq_lower = sym trap_fiq_lower,
+    serror_lower = sym trap_serror_lower,
+);
+
+/// 安装当前CPU的异常向量表，并清除当前任务
+pub fn setup() {
+    TPIDR_EL1.set(0);
+    let el = CurrentEL.read(CurrentEL::EL);
+    unsafe {
+        match el {
+            1 => asm!(
+                "adrp {0}, __sparreal_vectors",
+                "add  {0}, {0}, :lo12:__sparreal_vectors",
+                "msr  VBAR_EL1, {0}",
+                "isb",
+                out(reg) _,
+            ),
+            2 => asm!(
+                "adrp {0}, __sparreal_vectors",
+                "add  {0}, {0}, :lo12:__sparreal_vectors",
+                "msr  VBAR_EL2, {0}",
+                "isb",
+                out(reg) _,
+            ),
+            _ => panic!("Unsupported EL: {el}"),
+        }
+    }
+}
+
+/// 进入 EL0，`kstack_top` 作为此后陷入时的内核栈
+pub unsafe fn user_enter(pc: usize, user_sp: usize, kstack_top: usize, arg: usize) -> ! {
+    assert_eq!(
+        CurrentEL.read(CurrentEL::EL),
+        1,
+        "user mode requires the kernel to run at EL1"
+    );
+    unsafe {
+        asm!(
+            "msr  daifset, #2",
+            "msr  SP_EL0, {usp}",
+            "msr  ELR_EL1, {pc}",
+            "msr  SPSR_EL1, xzr",
+            // 载入的代码已写回数据缓存，丢弃本CPU上旧的指令缓存
+            "ic   iallu",
+            "dsb  ish",
+            "isb",
+            "mov  sp, {ksp}",
+            "mov  x1, xzr",
+            "mov  x2, xzr",
+            "mov  x3, xzr",
+            "mov  x4, xzr",
+            "mov  x5, xzr",
+            "mov  x6, xzr",
+            "mov  x7, xzr",
+            "mov  x8, xzr",
+            "mov  x9, xzr",
+            "mov  x10, xzr",
+            "mov  x11, xzr",
+            "mov  x12, xzr",
+            "mov  x13, xzr",
+            "mov  x14, xzr",
+            "mov  x15, xzr",
+            "mov  x16, xzr",
+            "mov  x17, xzr",
+            "mov  x18, xzr",
+            "mov  x19, xzr",
+            "mov  x20, xzr",
+            "mov  x21, xzr",
+            "mov  x22, xzr",
+            "mov  x23, xzr",
+            "mov  x24, xzr",
+            "mov  x25, xzr",
+            "mov  x26, xzr",
+            "mov  x27, xzr",
+            "mov  x28, xzr",
+            "mov  x29, xzr",
+            "mov  x30, xzr",
+            "eret",
+            usp = in(reg) user_sp,
+            pc = in(reg) pc,
+            ksp = in(reg) kstack_top,
+            in("x0") arg,
+            options(noreturn),
+        )
+    }
 }